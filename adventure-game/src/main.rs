@@ -1,5 +1,9 @@
 use assets_manager::{asset::Png, AssetCache};
+use engine::collision::Priority;
+use engine::dungeon::{self, DungeonSettings};
 use engine::level::Level;
+use engine::pathfinding::Connectivity;
+use engine::physics::Kinematic;
 use engine::Contact;
 use engine::Dir;
 use engine::Pos;
@@ -29,8 +33,10 @@ const EXPERIENCE: SheetRegion = SheetRegion::rect(525, 50, 8, 8);
 
 const ATK: SheetRegion = SheetRegion::rect(525, 19, 8, 8);
 const BLANK: SheetRegion = SheetRegion::rect(600, 600, 16, 16);
+const DOOR: SheetRegion = SheetRegion::rect(611, 55, 16, 16);
 
-const TILE_SZ: usize = 16;
+// size of HUD and menu sprites; things in the world are as big as the current level's tiles
+const UI_SZ: usize = 16;
 const W: usize = 220; // 320
 const H: usize = 140; // 240
 const SCREEN_FAST_MARGIN: f32 = 64.0;
@@ -38,16 +44,54 @@ const SCREEN_FAST_MARGIN: f32 = 64.0;
 // pixels per second
 const PLAYER_SPEED: f32 = 64.0;
 const ENEMY_SPEED: f32 = 32.0;
-const _KNOCKBACK_SPEED: f32 = 128.0;
+// pixels per second per second: how quickly things get up to speed and how quickly they slow down on plain ground
+const PLAYER_ACCEL: f32 = 1024.0;
+const PLAYER_FRICTION: f32 = 512.0;
+const ENEMY_ACCEL: f32 = 512.0;
+const ENEMY_FRICTION: f32 = 256.0;
+// how many tiles away an enemy will notice and chase the player
+const CHASE_RANGE: f32 = 6.0;
+// chance each frame of another enemy turning up, unless the level's metadata sets spawn_rate
+const SPAWN_RATE: f32 = 0.04;
+// how many tiles away the player can see through the fog
+const SIGHT_RADIUS: usize = 6;
+// how fast getting hit sends the player (or an enemy) flying
+const KNOCKBACK_SPEED: f32 = 128.0;
 
 const ATTACK_MAX_TIME: f32 = 0.3;
 const ATTACK_COOLDOWN_TIME: f32 = 0.1;
 const KNOCKBACK_TIME: f32 = 1.0;
+// the player can shove enemies around, but enemies can't shove the player (see World::spread_out)
+const PLAYER_PUSH: Priority = 2;
+const ENEMY_PUSH: Priority = 1;
 
 const DT: f32 = 1.0 / 60.0;
 
 const LEVELUP: u8 = 5;
 
+// rocks can be smashed with an attack, leaving open floor behind
+const BREAKABLE: &str = "*";
+const RUBBLE: &str = ".";
+
+// Doors to `floorN` lead into generated dungeon floors, built with the same tiles as the hand-made levels.
+// Set ADVENTURE_SEED to replay the same dungeon.
+const DUNGEON_HEADER: &str = "floor 16
+bg 594 55 2 2
+spawn_rate=0.06
+====
+. o 594 55 16 16
+# s 419 61 16 16
+* s 435 61 16 16
+";
+const DUNGEON: DungeonSettings = DungeonSettings {
+    width: 40,
+    height: 30,
+    rooms: 8,
+    room_size: (4, 8),
+    enemies: 6,
+    seed: 0,
+};
+
 fn main() {
     #[cfg(not(target_arch = "wasm32"))]
     let source =
@@ -62,10 +106,13 @@ struct AdventureGame {
     pub attack_area: Rect,
     pub attack_range: f32,
     pub attack_timer: f32,
+    // enemies the current swing has already hit, so each swing only hits them once
+    pub swing_hits: Vec<usize>,
     pub knockback_timer: f32,
     pub health: u8,
     pub xp: u8,
     pub upgrade: bool,
+    pub dungeon_seed: u64,
 }
 
 impl AdventureGame {
@@ -79,10 +126,15 @@ impl AdventureGame {
             },
             knockback_timer: 0.0,
             attack_timer: 0.0,
+            swing_hits: Vec::new(),
             attack_range: 3.0,
             health: 3,
             xp: 0,
             upgrade: false,
+            dungeon_seed: std::env::var("ADVENTURE_SEED")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(rand::random),
         };
         let player_start = *world.levels[world.current_level]
             .starts()
@@ -91,6 +143,11 @@ impl AdventureGame {
             .map(|(_, ploc)| ploc)
             .expect("Start level doesn't put the player anywhere");
         world.enter_level(player_start);
+        // dungeons start dark and are revealed as the player looks around
+        for level in world.levels.iter_mut() {
+            level.enable_fog();
+        }
+        world.levels[world.current_level].update_fog(player_start, SIGHT_RADIUS);
         game
     }
 
@@ -99,8 +156,8 @@ impl AdventureGame {
 
         // draw UI with health and experience
         let heart_pos = Transform {
-            w: TILE_SZ as u16,
-            h: TILE_SZ as u16,
+            w: UI_SZ as u16,
+            h: UI_SZ as u16,
             x: 10.0,
             y: 6.0,
             rot: 0.0,
@@ -109,15 +166,15 @@ impl AdventureGame {
             frend.draw_sprite(
                 1,
                 Transform {
-                    x: heart_pos.x + i as f32 * TILE_SZ as f32,
+                    x: heart_pos.x + i as f32 * UI_SZ as f32,
                     ..heart_pos
                 },
                 HEART.with_depth(1),
             );
         }
         let exp_pos = Transform {
-            w: TILE_SZ as u16,
-            h: TILE_SZ as u16,
+            w: UI_SZ as u16,
+            h: UI_SZ as u16,
             x: W as f32 - 10.0,
             y: H as f32 - 10.0,
             rot: 0.0,
@@ -134,10 +191,14 @@ impl AdventureGame {
         }
     }
     fn simulate(&mut self, world: &mut World, input: &Input, dt: f32) {
+        let tsz = world.level().tile_size();
         if !world.paused && !world.game_end {
             let mut rng = rand::thread_rng();
-            let rand = rng.gen_range(0..1000);
-            if rand > 960 {
+            let spawn_rate = world
+                .level()
+                .meta_or("spawn_rate", SPAWN_RATE)
+                .unwrap_or_else(|e| panic!("{e}"));
+            if rng.gen::<f32>() < spawn_rate {
                 world.spawn_enemies();
             }
         }
@@ -173,9 +234,9 @@ impl AdventureGame {
         if self.knockback_timer > 0.0 {
             self.knockback_timer -= dt;
         }
-        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight) * PLAYER_SPEED * DT;
+        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight);
         // now down means -y and up means +y!  beware!
-        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp) * PLAYER_SPEED * DT;
+        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp);
         let attacking = !self.attack_area.is_empty();
         let _knockback = self.knockback_timer > 0.0;
         if !attacking {
@@ -197,11 +258,32 @@ impl AdventureGame {
             // compute the attack area's center based on the player's position and facing and some offset
             // For the spritesheet provided, the attack is placed 8px "forwards" from the player.
             self.attack_timer = ATTACK_MAX_TIME;
+            self.swing_hits.clear();
             self.attack_area = Rect {
-                x: world.player.pos.x - (TILE_SZ as f32 * (self.attack_range / 2.0)),
-                y: world.player.pos.y - (TILE_SZ as f32 * (self.attack_range / 2.0)),
-                w: self.attack_range as u16 * TILE_SZ as u16,
-                h: self.attack_range as u16 * TILE_SZ as u16,
+                x: world.player.pos.x - (tsz as f32 * (self.attack_range / 2.0)),
+                y: world.player.pos.y - (tsz as f32 * (self.attack_range / 2.0)),
+                w: self.attack_range as u16 * tsz as u16,
+                h: self.attack_range as u16 * tsz as u16,
+            };
+            let level = &mut world.levels[world.current_level];
+            let broken: Vec<_> = level
+                .tiles_within(self.attack_area)
+                .filter_map(|(_, rect, _)| {
+                    let center = WorldPos {
+                        x: rect.x + rect.w as f32 / 2.0,
+                        y: rect.y + rect.h as f32 / 2.0,
+                    };
+                    self.attack_area
+                        .overlap(rect)
+                        .and_then(|_| level.tile_symbol_at(center))
+                        .filter(|sym| *sym == BREAKABLE)
+                        .map(|_| center)
+                })
+                .collect();
+            if level.has_symbol(RUBBLE) {
+                for pos in broken {
+                    level.set_tile_at(pos, RUBBLE);
+                }
             }
         } else if self.attack_timer <= ATTACK_COOLDOWN_TIME {
             // "turn off" the attack, but the cooldown is still going
@@ -212,98 +294,100 @@ impl AdventureGame {
                 h: 0,
             };
         }
-        let dest = world.player.pos + Vec2 { x: dx, y: dy };
-        if !world.level().get_tile_at(dest).unwrap().solid {
-            world.player.pos = dest;
-        }
+        world
+            .player
+            .motion
+            .accelerate(Vec2 { x: dx, y: dy } * PLAYER_ACCEL);
+        let level = &world.levels[world.current_level];
+        world
+            .player
+            .motion
+            .step(&mut world.player.pos, tsz as u16, level, dt);
         let mut rng = rand::thread_rng();
+        let player_cell = level.world_to_grid(world.player.pos);
         for enemy in world.enemies.iter_mut() {
-            if rng.gen_bool(0.05) {
-                enemy.0.dir = match rng.gen_range(0..4) {
-                    0 => Dir::N,
-                    1 => Dir::E,
-                    2 => Dir::S,
-                    3 => Dir::W,
-                    _ => panic!(),
-                };
-            }
-            let enemy_dest = enemy.0.pos + (enemy.0.dir.to_vec2() * ENEMY_SPEED * dt);
-            if (enemy_dest.x >= 0.0
-                && enemy_dest.x <= (world.levels[world.current_level].width() * TILE_SZ) as f32)
-                && (enemy_dest.y > 0.0
-                    && enemy_dest.y
-                        <= (world.levels[world.current_level].height() * TILE_SZ) as f32)
-            {
-                enemy.0.pos = enemy_dest;
-            }
+            // enemies that are close enough find their way around walls to the player, the rest wander
+            let to_player = world.player.pos - enemy.0.pos;
+            let chase = if to_player.mag_sq() < (CHASE_RANGE * tsz as f32).powi(2) {
+                level
+                    .find_path(
+                        level.world_to_grid(enemy.0.pos),
+                        player_cell,
+                        Connectivity::Four,
+                    )
+                    .map(|path| match level.path_to_world(&path).get(1) {
+                        Some(waypoint) => *waypoint - enemy.0.pos,
+                        None => to_player,
+                    })
+            } else {
+                None
+            };
+            let step = match chase {
+                Some(heading) if heading.mag_sq() > 1.0 => {
+                    enemy.0.dir = if heading.x.abs() > heading.y.abs() {
+                        if heading.x > 0.0 {
+                            Dir::E
+                        } else {
+                            Dir::W
+                        }
+                    } else if heading.y > 0.0 {
+                        Dir::N
+                    } else {
+                        Dir::S
+                    };
+                    heading.normalize()
+                }
+                Some(_) => Vec2 { x: 0.0, y: 0.0 },
+                None => {
+                    if rng.gen_bool(0.05) {
+                        enemy.0.dir = match rng.gen_range(0..4) {
+                            0 => Dir::N,
+                            1 => Dir::E,
+                            2 => Dir::S,
+                            3 => Dir::W,
+                            _ => panic!(),
+                        };
+                    }
+                    enemy.0.dir.to_vec2()
+                }
+            };
+            enemy.0.motion.accelerate(step * ENEMY_ACCEL);
+            enemy.0.motion.step(&mut enemy.0.pos, tsz as u16, level, dt);
         }
 
         let lw = world.level().width();
         let lh = world.level().height();
 
-        while world.player.pos.x
-            > world.camera.screen_pos[0] + world.camera.screen_size[0] - SCREEN_FAST_MARGIN
-        {
+        // scroll until the player is at least SCREEN_FAST_MARGIN away from the edges of the view
+        let on_screen = |world: &World| world.camera.to_screen(world.player.pos);
+        while on_screen(world).x > world.camera.screen_size[0] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] += 1.0;
         }
-        while world.player.pos.x < world.camera.screen_pos[0] + SCREEN_FAST_MARGIN {
+        while on_screen(world).x < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] -= 1.0;
         }
-        while world.player.pos.y
-            > world.camera.screen_pos[1] + world.camera.screen_size[1] - SCREEN_FAST_MARGIN
-        {
+        while on_screen(world).y > world.camera.screen_size[1] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] += 1.0;
         }
-        while world.player.pos.y < world.camera.screen_pos[1] + SCREEN_FAST_MARGIN {
+        while on_screen(world).y < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] -= 1.0;
         }
         world.camera.screen_pos[0] =
-            world.camera.screen_pos[0].clamp(0.0, (lw * TILE_SZ).max(W) as f32 - W as f32);
+            world.camera.screen_pos[0].clamp(0.0, (lw * tsz).max(W) as f32 - W as f32);
         world.camera.screen_pos[1] =
-            world.camera.screen_pos[1].clamp(0.0, (lh * TILE_SZ).max(H) as f32 - H as f32);
+            world.camera.screen_pos[1].clamp(0.0, (lh * tsz).max(H) as f32 - H as f32);
 
         let mut contacts = Vec::new();
         let p_rect = Rect {
-            x: world.player.pos.x - (TILE_SZ / 2) as f32,
-            y: world.player.pos.y - (TILE_SZ / 2) as f32,
-            w: (TILE_SZ) as u16,
-            h: (TILE_SZ) as u16,
+            x: world.player.pos.x - (tsz / 2) as f32,
+            y: world.player.pos.y - (tsz / 2) as f32,
+            w: (tsz) as u16,
+            h: (tsz) as u16,
         };
         let player = [p_rect, self.attack_area];
-        let enemy_rect: Vec<_> = world.enemies.iter().map(|e| make_rect(e.0.pos)).collect();
+        let enemy_rect: Vec<_> = world.enemies.iter().map(|e| make_rect(e.0.pos, tsz)).collect();
         generate_contact(&player, &enemy_rect, &mut contacts);
 
-        // Tile and Player contacts
-        let mut tile_contacts = Vec::new();
-        generate_tile_contact(&[player[0]], world.level(), &mut tile_contacts);
-
-        // Tile and Enemy contacts
-        let mut tile_enemy_contacts = Vec::new();
-        generate_tile_contact(&enemy_rect, world.level(), &mut tile_enemy_contacts);
-
-        // Contact Resolution for player vs. world
-        tile_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_contacts {
-            world.player.pos += find_displacement(p_rect, contact.b_rect);
-        }
-
-        // Contact Resolution for enemies vs. world
-        tile_enemy_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_enemy_contacts {
-            world.enemies[contact.a_index].0.pos +=
-                find_displacement(enemy_rect[contact.a_index], contact.b_rect);
-        }
-
         // For deleting enemies, it's best to add the enemy to a "to_remove" vec, and then remove those enemies after this loop is all done.
         contacts.sort_by(|a, b| {
             b.displacement
@@ -314,16 +398,27 @@ impl AdventureGame {
 
         let mut removable = Vec::new();
         for contact in contacts {
-            if contact.a_index == 1 && !removable.contains(&contact.b_index) {
-                world.enemies[contact.b_index].0.pos +=
-                    find_displacement(p_rect, enemy_rect[contact.b_index]);
-                removable.push(contact.b_index);
-                self.xp += 1; // this might be wrong as it gives xp when an enemy dies in a wall
-                              // dbg!(self.xp);
+            // each swing knocks an enemy back and takes off a hit point once, however many frames it touches
+            // the enemy for, and the enemy dies when it runs out
+            if contact.a_index == 1 && !self.swing_hits.contains(&contact.b_index) {
+                self.swing_hits.push(contact.b_index);
+                let enemy = &mut world.enemies[contact.b_index];
+                let away = world.levels[world.current_level].offset(world.player.pos, enemy.0.pos);
+                enemy.0.motion.impulse(knockback(away));
+                enemy.1 = enemy.1.saturating_sub(1);
+                if enemy.1 == 0 {
+                    removable.push(contact.b_index);
+                    self.xp += 1; // this might be wrong as it gives xp when an enemy dies in a wall
+                                  // dbg!(self.xp);
+                }
             }
             if contact.a_index == 0 {
                 if self.knockback_timer == 0.0 {
                     self.knockback_timer = KNOCKBACK_TIME;
+                    let away = world
+                        .level()
+                        .offset(world.enemies[contact.b_index].0.pos, world.player.pos);
+                    world.player.motion.impulse(knockback(away));
                     self.health -= 1;
                     if self.health == 0 {
                         world.game_end = true;
@@ -341,7 +436,74 @@ impl AdventureGame {
         removable.sort();
         for i in removable.iter().rev() {
             world.enemies.swap_remove(*i);
+            // the last enemy moves into the dead one's slot, so the swing remembers hitting it there
+            let moved = world.enemies.len();
+            self.swing_hits.retain(|j| j != i);
+            for j in self.swing_hits.iter_mut().filter(|j| **j == moved) {
+                *j = *i;
+            }
         }
+        world.spread_out(PLAYER_PUSH, ENEMY_PUSH, &[]);
+        self.take_door(world);
+        let player_pos = world.player.pos;
+        world.levels[world.current_level].update_fog(player_pos, SIGHT_RADIUS);
+    }
+    // Standing on a door takes the player to the level in its `to` property, at its to_x, to_y if it has them
+    // or else the level's player start.  Dungeon floors are generated the first time they're visited.
+    fn take_door(&mut self, world: &mut World) {
+        let level = world.level();
+        let player_cell = level.world_to_grid(world.player.pos);
+        let Some((door, _)) = level
+            .starts()
+            .iter()
+            .find(|(t, pos)| t.name() == "door" && level.world_to_grid(*pos) == player_cell)
+        else {
+            return;
+        };
+        let target: String = door
+            .prop("to")
+            .unwrap_or_else(|e| panic!("Door doesn't say which level it leads to: {e}"));
+        let to = if door.has_prop("to_x") || door.has_prop("to_y") {
+            let coord = |key| {
+                door.prop::<usize>(key)
+                    .unwrap_or_else(|e| panic!("Bad door arrival: {e}"))
+            };
+            Some(GridPos {
+                x: coord("to_x"),
+                y: coord("to_y"),
+            })
+        } else {
+            None
+        };
+        let idx = match world.levels.iter().position(|l| l.name() == target) {
+            Some(idx) => idx,
+            None => {
+                let floor = target
+                    .strip_prefix("floor")
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| panic!("Door leads to unknown level {target}"));
+                let settings = DungeonSettings {
+                    seed: self.dungeon_seed,
+                    ..DUNGEON
+                };
+                let mut level =
+                    dungeon::dungeon_level(&settings, floor, DUNGEON_HEADER, "#", ".", 0, 0);
+                level.enable_fog();
+                world.levels.push(level);
+                world.levels.len() - 1
+            }
+        };
+        world.current_level = idx;
+        // the new level has its own enemies
+        self.swing_hits.clear();
+        let level = world.level();
+        let arrival = match to {
+            Some(cell) => level.tile_center(cell),
+            None => level
+                .player_start()
+                .expect("Door leads to a level that doesn't put the player anywhere"),
+        };
+        world.enter_level(arrival);
     }
 }
 
@@ -350,18 +512,35 @@ impl engine::Game for AdventureGame {
         self.simulate(world, input, DT);
     }
     fn render(&mut self, world: &mut engine::World, frend: &mut Immediate) {
+        let tsz = world.level().tile_size();
         // make this exactly as big as we need
         frend.sprite_group_set_camera(0, world.camera);
 
-        world.level().render_immediate(frend);
+        world.level().render_visible(frend, &world.camera);
+
+        for (etype, pos) in world.level().starts() {
+            if etype.name() == "door" && world.level().is_visible_at(*pos) {
+                frend.draw_sprite(
+                    0,
+                    Transform {
+                        w: tsz as u16,
+                        h: tsz as u16,
+                        x: pos.x,
+                        y: pos.y,
+                        rot: 0.0,
+                    },
+                    DOOR.with_depth(4),
+                );
+            }
+        }
 
         for enemy in world.enemies.iter() {
-            if enemy.1 == 1 {
+            if enemy.1 > 0 && world.level().is_visible_at(enemy.0.pos) {
                 frend.draw_sprite(
                     0,
                     Transform {
-                        w: TILE_SZ as u16,
-                        h: TILE_SZ as u16,
+                        w: tsz as u16,
+                        h: tsz as u16,
                         x: enemy.0.pos.x,
                         y: enemy.0.pos.y,
                         rot: 0.0,
@@ -380,8 +559,8 @@ impl engine::Game for AdventureGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
+                    w: tsz as u16,
+                    h: tsz as u16,
                     x: world.player.pos.x,
                     y: world.player.pos.y,
                     rot: 0.0,
@@ -392,8 +571,8 @@ impl engine::Game for AdventureGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
+                    w: tsz as u16,
+                    h: tsz as u16,
                     x: world.player.pos.x,
                     y: world.player.pos.y,
                     rot: 0.0,
@@ -406,8 +585,8 @@ impl engine::Game for AdventureGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
+                    w: tsz as u16,
+                    h: tsz as u16,
                     x: world.player.pos.x,
                     y: world.player.pos.y,
                     rot: 0.0,
@@ -420,8 +599,8 @@ impl engine::Game for AdventureGame {
             // sprite_posns[1] = Transform::ZERO;
         } else {
             let (w, h) = match world.player.dir {
-                Dir::N | Dir::S => (16, 8),
-                _ => (8, 16),
+                Dir::N | Dir::S => (tsz as u16, tsz as u16 / 2),
+                _ => (tsz as u16 / 2, tsz as u16),
             };
             frend.draw_sprite(
                 0,
@@ -438,8 +617,8 @@ impl engine::Game for AdventureGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: (self.attack_range as usize * TILE_SZ) as u16,
-                    h: (self.attack_range as usize * TILE_SZ) as u16,
+                    w: (self.attack_range as usize * tsz) as u16,
+                    h: (self.attack_range as usize * tsz) as u16,
                     x: world.player.pos.x + 2.0,
                     y: world.player.pos.y - 2.0,
                     rot: 0.0,
@@ -474,15 +653,15 @@ impl engine::Game for AdventureGame {
                     repeat: frenderer::nineslice::Repeat::Tile,
                 },
             );
-            let pause_x = W as f32 / 2.0 - 4.0 * TILE_SZ as f32;
-            let pause_y = H as f32 / 2.0 - 3.0 * TILE_SZ as f32;
+            let pause_x = W as f32 / 2.0 - 4.0 * UI_SZ as f32;
+            let pause_y = H as f32 / 2.0 - 3.0 * UI_SZ as f32;
             frend.draw_nineslice(
                 1,
                 &nine_tiled,
                 pause_x,
                 pause_y,
-                8.0 * TILE_SZ as f32,
-                6.0 * TILE_SZ as f32,
+                8.0 * UI_SZ as f32,
+                6.0 * UI_SZ as f32,
                 0,
             );
 
@@ -502,23 +681,23 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.5 * TILE_SZ as f32,
-                        (H / 2) as f32 + TILE_SZ as f32,
+                        (W / 2) as f32 - 3.5 * UI_SZ as f32,
+                        (H / 2) as f32 + UI_SZ as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
                 let heart_pos = Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
-                    x: (W / 2) as f32 - 3.5 * TILE_SZ as f32,
+                    w: UI_SZ as u16,
+                    h: UI_SZ as u16,
+                    x: (W / 2) as f32 - 3.5 * UI_SZ as f32,
                     y: (H / 2) as f32,
                     rot: 0.0,
                 };
                 frend.draw_sprite(
                     1,
                     Transform {
-                        x: heart_pos.x as f32 * TILE_SZ as f32,
+                        x: heart_pos.x as f32 * UI_SZ as f32,
                         ..heart_pos
                     },
                     HEART.with_depth(1),
@@ -529,24 +708,24 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.5 * TILE_SZ as f32 + 10.0,
+                        (W / 2) as f32 - 3.5 * UI_SZ as f32 + 10.0,
                         (H / 2) as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
                 
                 let atk_pos = Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
-                    x: (W / 2) as f32 - 3.5 * TILE_SZ as f32,
-                    y: (H / 2) as f32 - TILE_SZ as f32,
+                    w: UI_SZ as u16,
+                    h: UI_SZ as u16,
+                    x: (W / 2) as f32 - 3.5 * UI_SZ as f32,
+                    y: (H / 2) as f32 - UI_SZ as f32,
                     rot: 0.0,
                 };
                 frend.draw_sprite(
                     0, // TODO: i think this group needs to change (might change others too)
                     Transform {
-                        x: atk_pos.x as f32 * TILE_SZ as f32,
+                        x: atk_pos.x as f32 * UI_SZ as f32,
                         ..atk_pos
                     },
                     ATK.with_depth(1),
@@ -557,11 +736,11 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.5 * TILE_SZ as f32 + 10.0,
-                        (H / 2) as f32 - TILE_SZ as f32,
+                        (W / 2) as f32 - 3.5 * UI_SZ as f32 + 10.0,
+                        (H / 2) as f32 - UI_SZ as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
             } else if world.game_end {
                 let text = "game over!";
@@ -570,11 +749,11 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.0 * TILE_SZ as f32,
-                        (H / 2) as f32 + TILE_SZ as f32,
+                        (W / 2) as f32 - 3.0 * UI_SZ as f32,
+                        (H / 2) as f32 + UI_SZ as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
             } else {
                 let mut text = "game paused!";
@@ -583,11 +762,11 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.0 * TILE_SZ as f32,
-                        (H / 2) as f32 + TILE_SZ as f32,
+                        (W / 2) as f32 - 3.0 * UI_SZ as f32,
+                        (H / 2) as f32 + UI_SZ as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
                 text = "unpause: Esc";
                 frend.draw_text(
@@ -595,11 +774,11 @@ impl engine::Game for AdventureGame {
                     &font,
                     text,
                     [
-                        (W / 2) as f32 - 3.25 * TILE_SZ as f32,
-                        (H / 2) as f32 - TILE_SZ as f32,
+                        (W / 2) as f32 - 3.25 * UI_SZ as f32,
+                        (H / 2) as f32 - UI_SZ as f32,
                     ],
                     0,
-                    (TILE_SZ / 2) as f32,
+                    (UI_SZ / 2) as f32,
                 );
             }
         }
@@ -616,39 +795,26 @@ impl engine::Game for AdventureGame {
             Some("tiles-sprites"),
         );
 
+        let load = |name: &str| {
+            cache
+                .load::<String>(name)
+                .unwrap_or_else(|_| panic!("Couldn't access {name}.txt"))
+                .read()
+                .to_string()
+        };
+        // levels can stamp prefabs from the content folder too
         let levels = vec![
-            Level::from_str(
-                &cache
-                    .load::<String>("level3")
-                    .expect("Couldn't access level3.txt")
-                    .read(),
-                0,
-                0,
-            ),
-            Level::from_str(
-                &cache
-                    .load::<String>("level1")
-                    .expect("Couldn't access level1.txt")
-                    .read(),
-                0,
-                0,
-            ),
-            Level::from_str(
-                &cache
-                    .load::<String>("level2")
-                    .expect("Couldn't access level2.txt")
-                    .read(),
-                0,
-                0,
-            ),
+            Level::from_str_with_prefabs(&load("level3"), 0, 0, &load),
+            Level::from_str_with_prefabs(&load("level1"), 0, 0, &load),
+            Level::from_str_with_prefabs(&load("level2"), 0, 0, &load),
         ];
         let current_level = 0;
         let camera = Camera2D {
             screen_pos: [0.0, 0.0],
             screen_size: [W as f32, H as f32],
         };
-        let sprite_estimate =
-            levels[current_level].sprite_count() + levels[current_level].starts().len();
+        let sprite_estimate = levels[current_level].sprite_count_for_view(&camera)
+            + levels[current_level].starts().len();
         // tile sprite group: 0
         renderer.sprite_group_add(&tile_tex, sprite_estimate, camera);
         // HUD sprite group: 1
@@ -663,14 +829,25 @@ impl engine::Game for AdventureGame {
         world.set_levels(levels);
         world.set_current_level(current_level);
         world.set_enemies(vec![]);
+        world.set_enemy_motion(Kinematic::new(ENEMY_SPEED, ENEMY_FRICTION));
         world.set_player(Pos {
             pos: player_start,
             dir: Dir::S,
+            motion: Kinematic::new(PLAYER_SPEED, PLAYER_FRICTION),
         });
         AdventureGame::new(world)
     }
 }
 
+// A shove at KNOCKBACK_SPEED in the direction of `away`, or none if the two things are right on top of each other.
+fn knockback(away: Vec2) -> Vec2 {
+    if away.mag_sq() > 0.0 {
+        away.normalize() * KNOCKBACK_SPEED
+    } else {
+        Vec2 { x: 0.0, y: 0.0 }
+    }
+}
+
 fn generate_contact(group_a: &[Rect], group_b: &[Rect], contacts: &mut Vec<Contact>) {
     for (a_i, a_rect) in group_a.iter().enumerate() {
         for (b_i, b_rect) in group_b.iter().enumerate() {
@@ -687,46 +864,12 @@ fn generate_contact(group_a: &[Rect], group_b: &[Rect], contacts: &mut Vec<Conta
     }
 }
 
-fn generate_tile_contact(group_a: &[Rect], lvl: &Level, contacts: &mut Vec<Contact>) {
-    for (a_i, a_rect) in group_a.iter().enumerate() {
-        for (_, b_rect, _) in lvl.tiles_within(*a_rect).filter(|(_, _r, td)| td.solid) {
-            if let Some(overlap) = a_rect.overlap(b_rect) {
-                contacts.push(Contact {
-                    displacement: overlap,
-                    a_index: a_i,
-                    _a_rect: *a_rect,
-                    b_index: 0,
-                    b_rect,
-                });
-            }
-        }
-    }
-}
-
-fn find_displacement(a: Rect, b: Rect) -> Vec2 {
-    if let Some(mut overlap) = a.overlap(b) {
-        if overlap.x < overlap.y {
-            overlap.y = 0.0;
-        } else {
-            overlap.x = 0.0;
-        }
-        if a.x < b.x {
-            overlap.x *= -1.0;
-        }
-        if a.y < b.y {
-            overlap.y *= -1.0;
-        }
-        overlap
-    } else {
-        Vec2 { x: 0.0, y: 0.0 }
-    }
-}
-
-fn make_rect(position: Vec2) -> Rect {
+// A tile-sized rect centered on position.
+fn make_rect(position: WorldPos, tsz: usize) -> Rect {
     Rect {
-        x: position.x - (TILE_SZ / 2) as f32,
-        y: position.y - (TILE_SZ / 2) as f32,
-        w: TILE_SZ as u16,
-        h: TILE_SZ as u16,
+        x: position.x - (tsz / 2) as f32,
+        y: position.y - (tsz / 2) as f32,
+        w: tsz as u16,
+        h: tsz as u16,
     }
 }
@@ -0,0 +1,33 @@
+// Converts a text level into the binary form read by Level::from_bytes:
+//   cargo run -p engine --bin compile_level -- content/level1.txt [content/level1.lvl]
+// Prefabs the level stamps are read from NAME.txt next to it.
+use engine::level::Level;
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let input = PathBuf::from(
+        args.next()
+            .expect("Usage: compile_level LEVEL.txt [OUTPUT.lvl]"),
+    );
+    let output = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| input.with_extension("lvl"));
+    let dir = input.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let read = |path: &Path| {
+        std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Couldn't read {}: {e}", path.display()))
+    };
+    let load_prefab = |name: &str| read(&dir.join(name).with_extension("txt"));
+    let level = Level::from_str_with_prefabs(&read(&input), 0, 0, &load_prefab);
+    let bytes = level.to_bytes();
+    std::fs::write(&output, &bytes)
+        .unwrap_or_else(|e| panic!("Couldn't write {}: {e}", output.display()));
+    println!(
+        "{} -> {} ({} bytes)",
+        input.display(),
+        output.display(),
+        bytes.len()
+    );
+}
@@ -0,0 +1,149 @@
+use crate::grid::{Coord, Grid};
+use crate::level::Level;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Debug)]
+pub struct CaveSettings {
+    // size in tiles, including the solid border
+    pub width: usize,
+    pub height: usize,
+    // chance (0 to 1) of each tile starting out as wall; around 0.45 gives caves, lower gives open arenas
+    pub fill: f32,
+    // how many rounds of smoothing to run; more gives rounder, blobbier caves
+    pub steps: usize,
+    // border tiles to knock out, each joined to the cave by a tunnel; the player starts in the first one
+    pub openings: Vec<Coord>,
+    // the same seed and settings always give the same cave
+    pub seed: u64,
+}
+
+// The tile grid of a cave: true is wall.  Random noise is smoothed out with the usual 4-5 rule
+// (a tile is wall if at least five of its eight neighbors are, or four and it already was),
+// then every open area but the biggest is filled in so the whole cave is connected.
+// The border stays solid except for the openings.
+pub fn generate(settings: &CaveSettings) -> Grid<bool> {
+    let (w, h) = (settings.width, settings.height);
+    assert!(
+        w >= 3 && h >= 3,
+        "Caves need at least one tile inside the border"
+    );
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let border = |x: usize, y: usize| x == 0 || y == 0 || x == w - 1 || y == h - 1;
+    let mut walls = Grid::new(
+        w,
+        h,
+        (0..w * h).map(|idx| border(idx % w, idx / w) || rng.gen::<f32>() < settings.fill),
+    );
+    for _step in 0..settings.steps {
+        walls = walls.map(|(x, y), &wall| {
+            // off the edge counts as wall
+            let around = walls.neighbors_8(x, y).filter(|&c| walls[c]).count()
+                + (8 - walls.neighbors_8(x, y).count());
+            border(x, y) || around >= 5 || (wall && around >= 4)
+        });
+    }
+    let (regions, count) = walls.label_regions(|_, wall| !*wall);
+    let mut sizes = vec![0; count];
+    for idx in 0..w * h {
+        if let Some(r) = regions[idx] {
+            sizes[r] += 1;
+        }
+    }
+    let biggest = (0..count).max_by_key(|&r| sizes[r]);
+    match biggest {
+        Some(biggest) => {
+            for idx in 0..w * h {
+                walls[idx] = regions[idx] != Some(biggest);
+            }
+        }
+        // everything filled in: dig out a single room in the middle so there's somewhere to stand
+        None => walls[(w / 2, h / 2)] = false,
+    }
+    for &opening in settings.openings.iter() {
+        assert!(
+            border(opening.0, opening.1),
+            "Cave opening {opening:?} isn't on the border"
+        );
+        tunnel(&mut walls, opening);
+    }
+    walls
+}
+
+// Opens a border tile and digs an L-shaped tunnel from just inside it to the nearest open tile.
+fn tunnel(walls: &mut Grid<bool>, (x, y): Coord) {
+    let (w, h) = (walls.width(), walls.height());
+    let inside = (x.clamp(1, w - 2), y.clamp(1, h - 2));
+    let target = walls
+        .iter_coords()
+        .filter(|(_, wall)| !**wall)
+        .map(|(c, _)| c)
+        .min_by_key(|&(cx, cy)| cx.abs_diff(inside.0) + cy.abs_diff(inside.1))
+        .unwrap_or(inside);
+    walls[(x, y)] = false;
+    for cx in inside.0.min(target.0)..=inside.0.max(target.0) {
+        walls[(cx, inside.1)] = false;
+    }
+    for cy in inside.1.min(target.1)..=inside.1.max(target.1) {
+        walls[(target.0, cy)] = false;
+    }
+}
+
+// A cave level with the player standing in the first opening, or in the open tile nearest the middle
+// if there are no openings.
+// The header is the metadata and legend of the level (see Level::from_symbols).
+pub fn cave_level(
+    settings: &CaveSettings,
+    header: &str,
+    wall: &str,
+    floor: &str,
+    sheet: usize,
+    sheet_layer: u16,
+) -> Level {
+    let walls = generate(settings);
+    let (w, h) = (walls.width(), walls.height());
+    let player = settings.openings.first().copied().unwrap_or_else(|| {
+        walls
+            .iter_coords()
+            .filter(|(_, wall)| !**wall)
+            .map(|(c, _)| c)
+            .min_by_key(|&(x, y)| x.abs_diff(w / 2) + y.abs_diff(h / 2))
+            .unwrap()
+    });
+    let symbols = walls.map(|_, &solid| if solid { wall } else { floor });
+    Level::from_symbols(header, &symbols, &[("player", player)], sheet, sheet_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::GridPos;
+    #[test]
+    fn test_cave_is_connected() {
+        let header = "cave 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n";
+        for seed in 0..20 {
+            let settings = CaveSettings {
+                width: 32,
+                height: 15,
+                fill: 0.45,
+                steps: 4,
+                openings: vec![(0, 7), (31, 7), (15, 0), (15, 14)],
+                seed,
+            };
+            let level = cave_level(&settings, header, "#", ".", 0, 0);
+            assert!(level.pockets().is_empty());
+            let mut exits = level.reachable_exits();
+            exits.sort();
+            assert_eq!(
+                exits,
+                vec![
+                    GridPos { x: 15, y: 0 },
+                    GridPos { x: 15, y: 14 },
+                    GridPos { x: 31, y: 7 }
+                ]
+            );
+            let (a, b) = (generate(&settings), generate(&settings));
+            assert!((0..32 * 15).all(|i| a[i] == b[i]));
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use crate::geom::{Rect, SweepHit, Vec2, WorldPos};
+use crate::level::Level;
+
+/*
+Keeps dynamic bodies (the player, enemies, knights, ...) from piling up on top of each other.  Games that want
+crowds to spread out make a Body for everything that should take up space after moving it, call separate, and
+then move each thing by however far its body was moved.
+
+When two bodies overlap they get pushed apart along whichever axis they overlap least.  Priorities decide who
+gives way: the body with the lower priority is moved all the way out of the other, and two with the same
+priority each move half way.  Solid tiles outrank every body, and a body that gets pushed out of a wall counts
+as a wall itself until separate returns, so e.g. the player (priority 2) can shove a skeleton (priority 1)
+around but can't squash it into a wall or push a line of them through one.
+
+Pairs are resolved one at a time in index order and the whole thing is repeated up to `iterations` times, so a
+crowd settles a bit more each frame instead of all at once; bodies that only touch are left alone, so a
+settled crowd stays still.  Nothing depends on anything but the bodies and the level, so the same bodies in
+the same places always end up in the same places.
+
+Separating only looks at where bodies ended up, so something fast (a thrown spear, a knocked back enemy) can
+go right through another body between one frame and the next.  sweep_rects finds what it would hit on the
+way instead, and physics::move_and_slide does the same against the level's tiles.
+*/
+
+pub type Priority = u8;
+// What solid tiles push with; a body with this priority never moves.
+pub const WALL: Priority = Priority::MAX;
+
+// How far two things can be inside each other and still count as just touching.
+const SLOP: f32 = 0.01;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Body {
+    pub rect: Rect,
+    pub priority: Priority,
+}
+
+impl Body {
+    // A w by h body centered on pos, which is how the games place their sprites.
+    pub fn centered(pos: WorldPos, w: u16, h: u16, priority: Priority) -> Self {
+        Self {
+            rect: Rect {
+                x: pos.x - (w / 2) as f32,
+                y: pos.y - (h / 2) as f32,
+                w,
+                h,
+            },
+            priority,
+        }
+    }
+    fn center(&self) -> WorldPos {
+        center(self.rect)
+    }
+    fn shift(&mut self, by: Vec2) {
+        self.rect.x += by.x;
+        self.rect.y += by.y;
+    }
+}
+
+fn center(rect: Rect) -> WorldPos {
+    WorldPos {
+        x: rect.x + rect.w as f32 / 2.0,
+        y: rect.y + rect.h as f32 / 2.0,
+    }
+}
+
+// Pushes overlapping bodies apart, and out of the level's solid tiles if there's a level.  Returns how far each
+// body moved, to add to the position of whatever it stands for.
+pub fn separate(bodies: &mut [Body], level: Option<&Level>, iterations: usize) -> Vec<Vec2> {
+    let start: Vec<_> = bodies.iter().map(|b| b.center()).collect();
+    let mut pinned = vec![false; bodies.len()];
+    for _ in 0..iterations {
+        let mut moved = false;
+        for i in 0..bodies.len() {
+            for j in i + 1..bodies.len() {
+                let Some(push) = push_out(
+                    bodies[i].center(),
+                    bodies[j].center(),
+                    bodies[i].rect,
+                    bodies[j].rect,
+                    level,
+                ) else {
+                    continue;
+                };
+                let rank = |k: usize| if pinned[k] { WALL } else { bodies[k].priority };
+                let (ri, rj) = (rank(i), rank(j));
+                let (share_i, share_j) = match ri.cmp(&rj) {
+                    _ if ri == WALL && rj == WALL => continue,
+                    std::cmp::Ordering::Less => (1.0, 0.0),
+                    std::cmp::Ordering::Greater => (0.0, 1.0),
+                    std::cmp::Ordering::Equal => (0.5, 0.5),
+                };
+                bodies[i].shift(push * share_i);
+                bodies[j].shift(push * -share_j);
+                // anything shoved by a wall (or something stuck against one) is stuck too
+                pinned[i] |= rj == WALL;
+                pinned[j] |= ri == WALL;
+                moved = true;
+            }
+        }
+        if let Some(level) = level {
+            for (body, pinned) in bodies.iter_mut().zip(pinned.iter_mut()) {
+                if body.priority == WALL {
+                    continue;
+                }
+                let tiles: Vec<_> = level
+                    .tiles_within(body.rect)
+                    .filter(|(_, _, tile)| tile.solid)
+                    .map(|(_, rect, _)| rect)
+                    .collect();
+                for tile in tiles {
+                    if let Some(push) =
+                        push_out(body.center(), center(tile), body.rect, tile, Some(level))
+                    {
+                        body.shift(push);
+                        *pinned = true;
+                        moved = true;
+                    }
+                }
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+    bodies
+        .iter()
+        .zip(start)
+        .map(|(body, start)| match level {
+            Some(level) => level.offset(start, body.center()),
+            None => body.center() - start,
+        })
+        .collect()
+}
+
+// The first of others that rect runs into if it moves by delta, and where it hits it.  Ties go to the
+// earliest in others.
+pub fn sweep_rects(rect: Rect, delta: Vec2, others: &[Rect]) -> Option<(usize, SweepHit)> {
+    others
+        .iter()
+        .enumerate()
+        .filter_map(|(i, other)| rect.sweep(delta, *other).map(|hit| (i, hit)))
+        .min_by(|(_, a), (_, b)| a.time.total_cmp(&b.time))
+}
+
+// How far a has to move to get out of b, along the axis they overlap least, or None if they only touch.
+// Measured the short way around on a wrapped level.  If their centers line up on that axis, a goes left or down.
+fn push_out(
+    a_center: WorldPos,
+    b_center: WorldPos,
+    a: Rect,
+    b: Rect,
+    level: Option<&Level>,
+) -> Option<Vec2> {
+    let d = match level {
+        Some(level) => level.offset(b_center, a_center),
+        None => a_center - b_center,
+    };
+    let overlap_x = (a.w + b.w) as f32 / 2.0 - d.x.abs();
+    let overlap_y = (a.h + b.h) as f32 / 2.0 - d.y.abs();
+    if overlap_x <= SLOP || overlap_y <= SLOP {
+        return None;
+    }
+    let away = |d: f32| if d > 0.0 { 1.0 } else { -1.0 };
+    if overlap_x < overlap_y {
+        Some(Vec2 {
+            x: overlap_x * away(d.x),
+            y: 0.0,
+        })
+    } else {
+        Some(Vec2 {
+            x: 0.0,
+            y: overlap_y * away(d.y),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn at(x: f32, y: f32, priority: Priority) -> Body {
+        Body::centered(WorldPos { x, y }, 16, 16, priority)
+    }
+    #[test]
+    fn test_equal_bodies_split_the_difference() {
+        let mut bodies = [at(0.0, 0.0, 1), at(10.0, 0.0, 1)];
+        let moved = separate(&mut bodies, None, 4);
+        assert_eq!(
+            moved,
+            vec![Vec2 { x: -3.0, y: 0.0 }, Vec2 { x: 3.0, y: 0.0 }]
+        );
+        // stacked exactly on top of each other, they still come apart, and always the same way
+        let mut stacked = [at(5.0, 5.0, 1), at(5.0, 5.0, 1)];
+        separate(&mut stacked, None, 4);
+        assert_eq!(stacked[0].rect.x, stacked[1].rect.x);
+        assert_eq!(stacked[0].rect.y + 16.0, stacked[1].rect.y);
+    }
+    #[test]
+    fn test_priorities() {
+        let mut bodies = [at(10.0, 0.0, 1), at(0.0, 0.0, 2)];
+        let moved = separate(&mut bodies, None, 4);
+        assert_eq!(moved[0], Vec2 { x: 6.0, y: 0.0 });
+        assert_eq!(moved[1], Vec2 { x: 0.0, y: 0.0 });
+    }
+    #[test]
+    fn test_cannot_shove_into_walls() {
+        let level = Level::from_str(
+            "room 4 1 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n. . . #\n",
+            0,
+            0,
+        );
+        // a skeleton resting against the wall, and the player walking into it from the left
+        let mut bodies = [at(40.0, 8.0, 1), at(30.0, 8.0, 2)];
+        separate(&mut bodies, Some(&level), 4);
+        assert_eq!(bodies[0].center(), WorldPos { x: 40.0, y: 8.0 });
+        assert_eq!(bodies[1].center(), WorldPos { x: 24.0, y: 8.0 });
+    }
+    #[test]
+    fn test_sweep_rects() {
+        // a spear moving a long way right passes a skeleton behind it and hits the nearer of two ahead
+        let spear = at(0.0, 0.0, 1).rect;
+        let others = [
+            at(-40.0, 0.0, 1).rect,
+            at(200.0, 4.0, 1).rect,
+            at(100.0, 0.0, 1).rect,
+        ];
+        let (i, hit) = sweep_rects(spear, Vec2 { x: 400.0, y: 0.0 }, &others).unwrap();
+        assert_eq!(i, 2);
+        assert_eq!(hit.time, 84.0 / 400.0);
+        assert_eq!(hit.normal, Vec2 { x: -1.0, y: 0.0 });
+        // but not one it only grazes on the way past
+        assert!(
+            sweep_rects(spear, Vec2 { x: 400.0, y: 0.0 }, &[at(100.0, 16.0, 1).rect]).is_none()
+        );
+    }
+    #[test]
+    fn test_crowd_settles() {
+        // twenty skeletons piled into a 4 by 5 heap spread out within a second, and then stay put
+        let mut bodies: Vec<_> = (0..20)
+            .map(|i| at((i % 5) as f32 * 1.5, (i / 5) as f32 * 2.0, 1))
+            .collect();
+        let mut frames = 0;
+        while separate(&mut bodies, None, 4)
+            .iter()
+            .any(|m| m.mag_sq() > 0.0)
+        {
+            frames += 1;
+            assert!(frames < 60, "crowd still moving after {frames} frames");
+        }
+        let settled = bodies.clone();
+        separate(&mut bodies, None, 4);
+        assert_eq!(bodies, settled);
+        for (i, a) in bodies.iter().enumerate() {
+            for b in &bodies[i + 1..] {
+                assert!(push_out(a.center(), b.center(), a.rect, b.rect, None).is_none());
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::grid::{Coord, Grid, GridRect};
+use crate::level::Level;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug)]
+pub struct DungeonSettings {
+    // size in tiles, including the solid border
+    pub width: usize,
+    pub height: usize,
+    // how many rooms to try to fit; fewer may fit on a small map
+    pub rooms: usize,
+    // smallest and largest room side, in tiles
+    pub room_size: (usize, usize),
+    // how many enemy starts to spread over the rooms the player doesn't start in
+    pub enemies: usize,
+    // floor n of a dungeon is generated from seed + n, so a seed gives the same sequence of floors
+    pub seed: u64,
+}
+
+// Rooms closer than `gap` tiles count as overlapping, so there's always a wall between them.
+fn overlaps(a: &GridRect, b: &GridRect, gap: usize) -> bool {
+    a.x < b.x + b.w + gap && b.x < a.x + a.w + gap && a.y < b.y + b.h + gap && b.y < a.y + a.h + gap
+}
+
+pub struct Dungeon {
+    // true is open floor
+    pub open: Grid<bool>,
+    // in the order they were connected up; the player starts in the first one
+    pub rooms: Vec<GridRect>,
+}
+
+// Scatters non-overlapping rooms over the map and joins each one to the one placed before it
+// with an L-shaped corridor, so every room can be reached from every other.
+pub fn generate(settings: &DungeonSettings, floor: usize) -> Dungeon {
+    let (min, max) = settings.room_size;
+    assert!(
+        min > 0 && min <= max && max + 2 <= settings.width.min(settings.height),
+        "Rooms of size {min} to {max} don't fit in the dungeon"
+    );
+    let mut rng = StdRng::seed_from_u64(settings.seed.wrapping_add(floor as u64));
+    let mut rooms: Vec<GridRect> = vec![];
+    for _attempt in 0..settings.rooms * 10 {
+        if rooms.len() == settings.rooms {
+            break;
+        }
+        let w = rng.gen_range(min..=max);
+        let h = rng.gen_range(min..=max);
+        let room = GridRect {
+            x: rng.gen_range(1..=settings.width - w - 1),
+            y: rng.gen_range(1..=settings.height - h - 1),
+            w,
+            h,
+        };
+        if !rooms.iter().any(|r| overlaps(r, &room, 1)) {
+            rooms.push(room);
+        }
+    }
+    let mut open = Grid::new(
+        settings.width,
+        settings.height,
+        vec![false; settings.width * settings.height],
+    );
+    for room in rooms.iter() {
+        for c in room.coords() {
+            open[c] = true;
+        }
+    }
+    for pair in rooms.windows(2) {
+        let (a, b) = (pair[0].center(), pair[1].center());
+        // bend either horizontally-then-vertically or the other way around
+        let corner = if rng.gen() { (b.0, a.1) } else { (a.0, b.1) };
+        for (from, to) in [(a, corner), (corner, b)] {
+            for x in from.0.min(to.0)..=from.0.max(to.0) {
+                for y in from.1.min(to.1)..=from.1.max(to.1) {
+                    open[(x, y)] = true;
+                }
+            }
+        }
+    }
+    Dungeon { open, rooms }
+}
+
+// Floor `floor` of a dungeon as a Level named `floorN`, with the player in the middle of the first room,
+// enemies spread over the other rooms and a door in the room furthest from the player (or the far corner of
+// the player's room, if it's the only one) leading to the start of the next floor
+// (`door X Y to=floorN+1 to_x=TO-X to_y=TO-Y`).
+// The header is the metadata and legend of the level (see Level::from_symbols); its name is replaced.
+pub fn dungeon_level(
+    settings: &DungeonSettings,
+    floor: usize,
+    header: &str,
+    wall: &str,
+    open: &str,
+    sheet: usize,
+    sheet_layer: u16,
+) -> Level {
+    let dungeon = generate(settings, floor);
+    let mut rng = StdRng::seed_from_u64(settings.seed.wrapping_add(floor as u64));
+    let first = dungeon.rooms[0];
+    let player = first.center();
+    let dist = |(x, y): Coord| x.abs_diff(player.0) + y.abs_diff(player.1);
+    let door = if dungeon.rooms.len() > 1 {
+        dungeon
+            .rooms
+            .iter()
+            .max_by_key(|r| dist(r.center()))
+            .unwrap()
+            .center()
+    } else {
+        // the door can't go where the player starts, or they'd leave as soon as they arrived
+        first.coords().max_by_key(|c| dist(*c)).unwrap()
+    };
+    assert_ne!(door, player, "Dungeon floor {floor} has no room for a door");
+    let next_start = generate(settings, floor + 1).rooms[0].center();
+    let door_start = format!(
+        "door to=floor{} to_x={} to_y={}",
+        floor + 1,
+        next_start.0,
+        next_start.1
+    );
+    let mut starts = vec![("player".to_string(), player), (door_start, door)];
+    let mut taken: HashSet<Coord> = HashSet::from([player, door]);
+    // a one-room dungeon has nowhere else to put enemies
+    let others: Vec<GridRect> = if dungeon.rooms.len() > 1 {
+        dungeon.rooms[1..].to_vec()
+    } else {
+        dungeon.rooms.clone()
+    };
+    for i in 0..settings.enemies {
+        let room = others[i % others.len()];
+        let free: Vec<Coord> = room
+            .coords()
+            .filter(|c| !taken.contains(c) && !first.contains(*c))
+            .collect();
+        if let Some(&c) = free.choose(&mut rng) {
+            taken.insert(c);
+            starts.push(("enemy".to_string(), c));
+        }
+    }
+    let symbols = dungeon.open.map(|_, &o| if o { open } else { wall });
+    let mut header_lines = header.lines();
+    let name_line = header_lines
+        .next()
+        .expect("Dungeon header has no name line");
+    let tsz = name_line
+        .split_whitespace()
+        .nth(1)
+        .expect("No tile size in dungeon header {name_line}");
+    let mut renamed = format!("floor{floor} {tsz}\n");
+    for line in header_lines {
+        renamed.push_str(line);
+        renamed.push('\n');
+    }
+    let starts: Vec<(&str, Coord)> = starts.iter().map(|(s, c)| (s.as_str(), *c)).collect();
+    Level::from_symbols(&renamed, &symbols, &starts, sheet, sheet_layer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const HEADER: &str = "whatever 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n";
+    const SETTINGS: DungeonSettings = DungeonSettings {
+        width: 40,
+        height: 30,
+        rooms: 8,
+        room_size: (4, 8),
+        enemies: 6,
+        seed: 99,
+    };
+    #[test]
+    fn test_dungeon_is_connected() {
+        for floor in 1..20 {
+            let level = dungeon_level(&SETTINGS, floor, HEADER, "#", ".", 0, 0);
+            assert_eq!(level.name(), format!("floor{floor}"));
+            assert!(level.pockets().is_empty());
+            assert!(level.unreachable_starts().is_empty());
+            let enemies = level.starts().iter().filter(|(t, _)| t.name() == "enemy");
+            assert_eq!(enemies.count(), 6);
+            let first = generate(&SETTINGS, floor).rooms[0];
+            assert_eq!(
+                level.world_to_grid(level.player_start().unwrap()),
+                first.center().into()
+            );
+        }
+    }
+    #[test]
+    fn test_one_room_dungeon() {
+        let settings = DungeonSettings {
+            rooms: 1,
+            ..SETTINGS
+        };
+        let level = dungeon_level(&settings, 1, HEADER, "#", ".", 0, 0);
+        let room = generate(&settings, 1).rooms[0];
+        let player = level.world_to_grid(level.player_start().unwrap());
+        let (_, door) = level
+            .starts()
+            .iter()
+            .find(|(t, _)| t.name() == "door")
+            .unwrap();
+        let door = level.world_to_grid(*door);
+        assert!(room.contains(door.into()));
+        assert_ne!(door, player);
+    }
+    #[test]
+    fn test_dungeon_seed() {
+        let a = generate(&SETTINGS, 3);
+        let b = generate(&SETTINGS, 3);
+        assert_eq!(a.rooms, b.rooms);
+        assert_ne!(a.rooms, generate(&SETTINGS, 4).rooms);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::grid::{Coord, Grid};
+use std::collections::HashSet;
+
+// Octant transforms for shadowcasting: each one maps the (dx, dy) of the first octant onto one of the eight.
+const OCTANTS: [[isize; 4]; 8] = [
+    [1, 0, 0, 1],
+    [0, 1, 1, 0],
+    [0, -1, 1, 0],
+    [-1, 0, 0, 1],
+    [-1, 0, 0, -1],
+    [0, -1, -1, 0],
+    [0, 1, -1, 0],
+    [1, 0, 0, -1],
+];
+
+// All the cells visible from origin within radius (a circle, not a square), using recursive
+// shadowcasting.  Blocking cells are visible themselves, so you can see the walls of a room.
+// Cells off the edge of the grid block sight.
+pub fn field_of_view<T>(
+    grid: &Grid<T>,
+    origin: Coord,
+    radius: usize,
+    blocks: impl Fn(Coord, &T) -> bool,
+) -> HashSet<Coord> {
+    let mut visible = HashSet::new();
+    if !grid.contains(origin.0, origin.1) {
+        return visible;
+    }
+    visible.insert(origin);
+    let caster = Caster {
+        grid,
+        origin,
+        radius,
+        blocks: &blocks,
+    };
+    for transform in OCTANTS {
+        caster.cast_light(1, 1.0, 0.0, transform, &mut visible);
+    }
+    visible
+}
+
+struct Caster<'a, T, F: Fn(Coord, &T) -> bool> {
+    grid: &'a Grid<T>,
+    origin: Coord,
+    radius: usize,
+    blocks: &'a F,
+}
+
+impl<T, F: Fn(Coord, &T) -> bool> Caster<'_, T, F> {
+    fn cell(&self, dx: isize, dy: isize, [xx, xy, yx, yy]: [isize; 4]) -> Option<Coord> {
+        let x = self.origin.0 as isize + dx * xx + dy * xy;
+        let y = self.origin.1 as isize + dx * yx + dy * yy;
+        if x >= 0 && y >= 0 && self.grid.contains(x as usize, y as usize) {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+    fn opaque(&self, cell: Option<Coord>) -> bool {
+        cell.is_none_or(|c| (self.blocks)(c, &self.grid[c]))
+    }
+    // Scans rows outward from the origin, keeping track of the range of slopes (start down to end)
+    // that is still lit, and recursing whenever a wall splits that range in two.
+    fn cast_light(
+        &self,
+        row: usize,
+        mut start: f32,
+        end: f32,
+        transform: [isize; 4],
+        visible: &mut HashSet<Coord>,
+    ) {
+        if start < end {
+            return;
+        }
+        let r2 = (self.radius * self.radius) as isize;
+        let mut new_start = 0.0;
+        for j in row..=self.radius {
+            let dy = -(j as isize);
+            let mut blocked = false;
+            for dx in -(j as isize)..=0 {
+                let l_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let r_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < r_slope {
+                    continue;
+                } else if end > l_slope {
+                    break;
+                }
+                let cell = self.cell(dx, dy, transform);
+                if let Some(c) = cell {
+                    if dx * dx + dy * dy <= r2 {
+                        visible.insert(c);
+                    }
+                }
+                if blocked {
+                    if self.opaque(cell) {
+                        new_start = r_slope;
+                    } else {
+                        blocked = false;
+                        start = new_start;
+                    }
+                } else if self.opaque(cell) && j < self.radius {
+                    blocked = true;
+                    self.cast_light(j + 1, start, l_slope, transform, visible);
+                    new_start = r_slope;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+// Remembers which cells of a level have ever been seen and which can be seen right now.
+#[derive(Clone)]
+pub struct Fog {
+    explored: Grid<bool>,
+    visible: Grid<bool>,
+}
+
+impl Fog {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            explored: Grid::new(width, height, vec![false; width * height]),
+            visible: Grid::new(width, height, vec![false; width * height]),
+        }
+    }
+    // Replaces what's visible now and adds it to what's been explored.
+    pub fn update(&mut self, visible: &HashSet<Coord>) {
+        for idx in 0..self.visible.width() * self.visible.height() {
+            self.visible[idx] = false;
+        }
+        for &c in visible {
+            if let Some(v) = self.visible.get_mut(c.0, c.1) {
+                *v = true;
+                self.explored[c] = true;
+            }
+        }
+    }
+    pub fn is_visible(&self, coord: Coord) -> bool {
+        self.visible.get(coord.0, coord.1).copied().unwrap_or(false)
+    }
+    pub fn is_explored(&self, coord: Coord) -> bool {
+        self.explored
+            .get(coord.0, coord.1)
+            .copied()
+            .unwrap_or(false)
+    }
+    // Forget everything, e.g. when the level is entered again.
+    pub fn clear(&mut self) {
+        *self = Self::new(self.visible.width(), self.visible.height());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn walls(rows: &[&str]) -> Grid<bool> {
+        Grid::new(
+            rows[0].len(),
+            rows.len(),
+            rows.iter().flat_map(|r| r.chars().map(|c| c == '#')),
+        )
+    }
+    #[test]
+    fn test_fov_stops_at_walls() {
+        let grid = walls(&[".......", "...#...", ".......", "......."]);
+        let seen = field_of_view(&grid, (3, 3), 10, |_, s| *s);
+        // the wall is visible but the cell right behind it isn't
+        assert!(seen.contains(&(3, 1)));
+        assert!(!seen.contains(&(3, 0)));
+        assert!(seen.contains(&(0, 0)));
+        assert!(seen.contains(&(6, 3)));
+        let near = field_of_view(&grid, (3, 3), 1, |_, s| *s);
+        assert!(near.contains(&(2, 3)));
+        assert!(!near.contains(&(1, 3)));
+    }
+    #[test]
+    fn test_fog_memory() {
+        let mut fog = Fog::new(4, 1);
+        fog.update(&HashSet::from([(0, 0), (1, 0)]));
+        fog.update(&HashSet::from([(1, 0)]));
+        assert!(!fog.is_visible((0, 0)));
+        assert!(fog.is_explored((0, 0)));
+        assert!(fog.is_visible((1, 0)));
+        assert!(!fog.is_explored((3, 0)));
+    }
+}
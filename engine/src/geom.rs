@@ -1,3 +1,21 @@
+use crate::grid;
+use frenderer::sprites::Camera2D;
+
+/*
+Positions come in three spaces, and each has its own type so they can't be mixed up by accident:
+
+- WorldPos: pixels from the bottom left of the level, with y going up.  Entities, starts and collision rects live here.
+- GridPos: a cell of the level's grid, column then row, with rows going down from the top.
+  Level::world_to_grid finds the cell a WorldPos is in, and Level::grid_to_world (a cell's bottom left corner)
+  or Level::tile_center go the other way.  Level's methods take and return GridPos; the grid algorithms
+  underneath it (grid, pathfinding, fov, raycast's RayHit, the level generators, ...) index plain grid::Coord
+  tuples, and From goes between the two.
+- ScreenPos: pixels from the bottom left of the camera's view, with y going up.  Use CameraSpace::to_screen
+  and to_world to get between it and world space.
+
+Vec2 is for offsets (velocities, displacements, directions).  A position plus a Vec2 is another position
+in the same space, and the difference between two positions is a Vec2.
+*/
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub struct Vec2 {
     pub x: f32,
@@ -10,14 +28,29 @@ pub struct Rect {
     pub w: u16,
     pub h: u16,
 }
+
+// Where a moving rect first runs into another: `time` is how far along the move it gets (0 is where it started,
+// 1 is the whole way) and `normal` points out of the face it hit, back toward where it came from.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SweepHit {
+    pub time: f32,
+    pub normal: Vec2,
+}
+
+// Gaps this small count as touching when sweeping, so something that stopped a hair inside a wall (from
+// rounding) doesn't count as already being in it and pass straight through.
+const SWEEP_SLOP: f32 = 0.001;
+
 #[allow(dead_code)]
 impl Rect {
+    // How far two rects overlap on each axis, or None if they don't.  Rects that only touch along an edge
+    // don't overlap, so something resting against a wall isn't colliding with it.
     pub fn overlap(&self, other: Rect) -> Option<Vec2> {
         let x_overlap =
             (self.x + self.w as f32).min(other.x + other.w as f32) - self.x.max(other.x);
         let y_overlap =
             (self.y + self.h as f32).min(other.y + other.h as f32) - self.y.max(other.y);
-        if x_overlap >= 0.0 && y_overlap >= 0.0 {
+        if x_overlap > 0.0 && y_overlap > 0.0 {
             // This will return the magnitude of overlap in each axis.
             Some(Vec2 {
                 x: x_overlap,
@@ -27,8 +60,8 @@ impl Rect {
             None
         }
     }
-    pub fn origin(&self) -> Vec2 {
-        Vec2 {
+    pub fn origin(&self) -> WorldPos {
+        WorldPos {
             x: self.x,
             y: self.y,
         }
@@ -36,6 +69,47 @@ impl Rect {
     pub fn is_empty(&self) -> bool {
         self.w == 0 || self.h == 0
     }
+    // Where this rect first hits `other` if it moves by delta, however far that is, so nothing can skip over
+    // a thin wall by moving fast.  Touching and moving into other is a hit at time 0, but touching and moving
+    // along or away from it isn't a hit, and neither is anything this rect already overlaps (so things that
+    // get stuck inside each other can get back out).
+    pub fn sweep(&self, delta: Vec2, other: Rect) -> Option<SweepHit> {
+        let (x_in, x_out) = slab(self.x, self.w, other.x, other.w, delta.x)?;
+        let (y_in, y_out) = slab(self.y, self.h, other.y, other.h, delta.y)?;
+        let (time, exit) = (x_in.max(y_in), x_out.min(y_out));
+        if !(0.0..=1.0).contains(&time) || time >= exit {
+            return None;
+        }
+        // the face it hit is on whichever axis it was last to line up on
+        let normal = if x_in > y_in {
+            Vec2 {
+                x: -delta.x.signum(),
+                y: 0.0,
+            }
+        } else {
+            Vec2 {
+                x: 0.0,
+                y: -delta.y.signum(),
+            }
+        };
+        Some(SweepHit { time, normal })
+    }
+}
+
+// When a span [a, a+a_len) moving by d starts and stops overlapping [b, b+b_len), as fractions of the move.
+// Infinitely long if it isn't moving and overlaps the whole time, and None if it never overlaps.
+fn slab(a: f32, a_len: u16, b: f32, b_len: u16, d: f32) -> Option<(f32, f32)> {
+    let (a_end, b_end) = (a + a_len as f32, b + b_len as f32);
+    let touching = |gap: f32| if gap > -SWEEP_SLOP { gap.max(0.0) } else { gap };
+    if d > 0.0 {
+        Some((touching(b - a_end) / d, (b_end - a) / d))
+    } else if d < 0.0 {
+        Some((touching(a - b_end) / -d, (a_end - b) / -d))
+    } else if a_end - b > SWEEP_SLOP && b_end - a > SWEEP_SLOP {
+        Some((f32::NEG_INFINITY, f32::INFINITY))
+    } else {
+        None
+    }
 }
 impl std::ops::Add for Vec2 {
     type Output = Vec2;
@@ -85,4 +159,86 @@ impl Vec2 {
         }
     }
 
-}
\ No newline at end of file
+    pub fn dot(&self, other: Vec2) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct WorldPos {
+    pub x: f32,
+    pub y: f32,
+}
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct ScreenPos {
+    pub x: f32,
+    pub y: f32,
+}
+macro_rules! position_ops {
+    ($pos:ty) => {
+        impl std::ops::Add<Vec2> for $pos {
+            type Output = $pos;
+            fn add(self, rhs: Vec2) -> $pos {
+                Self {
+                    x: self.x + rhs.x,
+                    y: self.y + rhs.y,
+                }
+            }
+        }
+        impl std::ops::AddAssign<Vec2> for $pos {
+            fn add_assign(&mut self, rhs: Vec2) {
+                self.x += rhs.x;
+                self.y += rhs.y;
+            }
+        }
+        impl std::ops::Sub for $pos {
+            type Output = Vec2;
+            fn sub(self, rhs: $pos) -> Vec2 {
+                Vec2 {
+                    x: self.x - rhs.x,
+                    y: self.y - rhs.y,
+                }
+            }
+        }
+    };
+}
+position_ops!(WorldPos);
+position_ops!(ScreenPos);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Default)]
+pub struct GridPos {
+    pub x: usize,
+    pub y: usize,
+}
+impl From<grid::Coord> for GridPos {
+    fn from((x, y): grid::Coord) -> Self {
+        Self { x, y }
+    }
+}
+impl From<GridPos> for grid::Coord {
+    fn from(pos: GridPos) -> Self {
+        (pos.x, pos.y)
+    }
+}
+
+pub trait CameraSpace {
+    // Where a point in the world shows up in the camera's view.
+    fn to_screen(&self, pos: WorldPos) -> ScreenPos;
+    // The point in the world under a point in the camera's view.
+    fn to_world(&self, pos: ScreenPos) -> WorldPos;
+}
+impl CameraSpace for Camera2D {
+    fn to_screen(&self, pos: WorldPos) -> ScreenPos {
+        ScreenPos {
+            x: pos.x - self.screen_pos[0],
+            y: pos.y - self.screen_pos[1],
+        }
+    }
+    fn to_world(&self, pos: ScreenPos) -> WorldPos {
+        WorldPos {
+            x: pos.x + self.screen_pos[0],
+            y: pos.y + self.screen_pos[1],
+        }
+    }
+}
@@ -1,6 +1,43 @@
 pub type Coord = (usize, usize);
 
+// A rectangle of cells: x..x+w by y..y+h.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GridRect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl GridRect {
+    pub fn center(&self) -> Coord {
+        (self.x + self.w / 2, self.y + self.h / 2)
+    }
+    pub fn contains(&self, (x, y): Coord) -> bool {
+        x >= self.x && x < self.x + self.w && y >= self.y && y < self.y + self.h
+    }
+    // Row by row, like grid storage.
+    pub fn coords(&self) -> impl Iterator<Item = Coord> {
+        let GridRect { x, y, w, h } = *self;
+        (y..y + h).flat_map(move |y| (x..x + w).map(move |x| (x, y)))
+    }
+    // The part of this rect that's inside both rects (possibly zero-sized).
+    pub fn intersect(&self, other: GridRect) -> GridRect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = (self.x + self.w).min(other.x + other.w);
+        let bottom = (self.y + self.h).min(other.y + other.h);
+        GridRect {
+            x,
+            y,
+            w: right.saturating_sub(x),
+            h: bottom.saturating_sub(y),
+        }
+    }
+}
+
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct Grid<T> {
     width: usize,
     height: usize,
@@ -129,6 +166,193 @@ impl<T> Grid<T> {
                 .and_then(|(x, y)| if x < w && y < h { Some((x, y)) } else { None })
         })
     }
+    // All the cells that can be reached from start by stepping between 4-neighbors that are `open`.
+    // Empty if start itself isn't open.
+    pub fn flood_fill(&self, start: Coord, open: impl Fn(Coord, &T) -> bool) -> Vec<Coord> {
+        let mut seen = vec![false; self.storage.len()];
+        let mut found = vec![];
+        let Some(idx) = self.coord_to_index(start) else {
+            return found;
+        };
+        if !open(start, &self.storage[idx]) {
+            return found;
+        }
+        seen[idx] = true;
+        let mut stack = vec![start];
+        while let Some((x, y)) = stack.pop() {
+            found.push((x, y));
+            for n in self.neighbors_4(x, y) {
+                let nidx = self.coord_to_index(n).unwrap();
+                if !seen[nidx] && open(n, &self.storage[nidx]) {
+                    seen[nidx] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        found
+    }
+    // Gives every connected group of `open` cells its own number, counting up from 0.
+    // Returns the labels (None for cells that aren't open) and how many regions there are.
+    pub fn label_regions(&self, open: impl Fn(Coord, &T) -> bool) -> (Grid<Option<usize>>, usize) {
+        let mut labels = Grid::new(self.width, self.height, vec![None; self.storage.len()]);
+        let mut count = 0;
+        for idx in 0..self.storage.len() {
+            let c = self.index_to_coord(idx).unwrap();
+            if labels[idx].is_none() && open(c, &self.storage[idx]) {
+                for cell in self.flood_fill(c, &open) {
+                    labels[cell] = Some(count);
+                }
+                count += 1;
+            }
+        }
+        (labels, count)
+    }
+    pub fn bounds(&self) -> GridRect {
+        GridRect {
+            x: 0,
+            y: 0,
+            w: self.width,
+            h: self.height,
+        }
+    }
+    // Every cell with its coordinate, row by row.
+    pub fn iter_coords(&self) -> impl Iterator<Item = (Coord, &T)> {
+        let w = self.width;
+        self.storage
+            .iter()
+            .enumerate()
+            .map(move |(idx, t)| ((idx % w, idx / w), t))
+    }
+    pub fn iter_coords_mut(&mut self) -> impl Iterator<Item = (Coord, &mut T)> {
+        let w = self.width;
+        self.storage
+            .iter_mut()
+            .enumerate()
+            .map(move |(idx, t)| ((idx % w, idx / w), t))
+    }
+    // The cells of rect (clipped to the grid) with their coordinates, row by row.
+    pub fn iter_region(&self, rect: GridRect) -> impl Iterator<Item = (Coord, &T)> {
+        rect.intersect(self.bounds())
+            .coords()
+            .map(|c| (c, &self[c]))
+    }
+    // A same-sized grid made by calling f on every cell.
+    pub fn map<U>(&self, mut f: impl FnMut(Coord, &T) -> U) -> Grid<U> {
+        Grid::new(
+            self.width,
+            self.height,
+            self.iter_coords().map(|(c, t)| f(c, t)),
+        )
+    }
+    // Like blit, but f decides how each source cell lands on the destination cell (e.g. skipping blanks).
+    pub fn blit_with<U>(&mut self, src: &Grid<U>, at: Coord, mut f: impl FnMut(&mut T, &U)) {
+        for ((x, y), t) in src.iter_coords() {
+            if let Some(dst) = self.get_mut(x + at.0, y + at.1) {
+                f(dst, t);
+            }
+        }
+    }
+    // A borrowed window onto rect, which must fit inside the grid; coordinates in the view start at its top left.
+    pub fn view(&self, rect: GridRect) -> GridView<'_, T> {
+        assert_eq!(
+            rect.intersect(self.bounds()),
+            rect,
+            "View {rect:?} doesn't fit in a {}x{} grid",
+            self.width,
+            self.height
+        );
+        GridView { grid: self, rect }
+    }
+}
+
+impl<T: Clone> Grid<T> {
+    // A copy of the cells in rect, which must fit inside the grid.
+    pub fn sub_grid(&self, rect: GridRect) -> Grid<T> {
+        self.view(rect).to_grid()
+    }
+    // Copies src into this grid with its top left at `at`; whatever hangs off the edge is dropped.
+    pub fn blit(&mut self, src: &Grid<T>, at: Coord) {
+        self.blit_with(src, at, |dst, t| *dst = t.clone());
+    }
+    // A new grid of the given size with this one in its top left corner and `fill` everywhere else.
+    pub fn resized(&self, width: usize, height: usize, fill: T) -> Grid<T> {
+        let mut grid = Grid::new(width, height, vec![fill; width * height]);
+        grid.blit(self, (0, 0));
+        grid
+    }
+    // Turned a quarter turn clockwise, so the left column becomes the top row.
+    pub fn rotated_cw(&self) -> Grid<T> {
+        let (w, h) = (self.width, self.height);
+        Grid::new(
+            h,
+            w,
+            (0..w * h).map(|idx| {
+                let (x, y) = (idx % h, idx / h);
+                self[(y, h - 1 - x)].clone()
+            }),
+        )
+    }
+    pub fn rotated_ccw(&self) -> Grid<T> {
+        let (w, h) = (self.width, self.height);
+        Grid::new(
+            h,
+            w,
+            (0..w * h).map(|idx| {
+                let (x, y) = (idx % h, idx / h);
+                self[(w - 1 - y, x)].clone()
+            }),
+        )
+    }
+    // Mirrored left to right.
+    pub fn flipped_h(&self) -> Grid<T> {
+        self.map(|(x, y), _| self[(self.width - 1 - x, y)].clone())
+    }
+    // Mirrored top to bottom.
+    pub fn flipped_v(&self) -> Grid<T> {
+        self.map(|(x, y), _| self[(x, self.height - 1 - y)].clone())
+    }
+}
+
+// A rectangular window onto part of a grid, made with Grid::view.
+pub struct GridView<'a, T> {
+    grid: &'a Grid<T>,
+    rect: GridRect,
+}
+
+impl<'a, T> GridView<'a, T> {
+    pub fn width(&self) -> usize {
+        self.rect.w
+    }
+    pub fn height(&self) -> usize {
+        self.rect.h
+    }
+    // Where the view's top left is in the underlying grid.
+    pub fn rect(&self) -> GridRect {
+        self.rect
+    }
+    pub fn get(&self, x: usize, y: usize) -> Option<&'a T> {
+        if x < self.rect.w && y < self.rect.h {
+            self.grid.get(self.rect.x + x, self.rect.y + y)
+        } else {
+            None
+        }
+    }
+    // Cells with coordinates relative to the view, row by row.
+    pub fn iter_coords(&self) -> impl Iterator<Item = (Coord, &'a T)> + '_ {
+        self.rect
+            .coords()
+            .map(|(x, y)| ((x - self.rect.x, y - self.rect.y), &self.grid[(x, y)]))
+    }
+}
+
+impl<T: Clone> GridView<'_, T> {
+    pub fn to_grid(&self) -> Grid<T> {
+        Grid::new(
+            self.rect.w,
+            self.rect.h,
+            self.iter_coords().map(|(_, t)| t.clone()),
+        )
+    }
 }
 
 impl<T> std::ops::Index<usize> for Grid<T> {
@@ -184,6 +408,63 @@ mod tests {
         }
     }
     #[test]
+    fn test_regions() {
+        #[rustfmt::skip]
+        let grid = Grid::new(4, 3, vec![
+            0, 0, 1, 0,
+            1, 1, 1, 0,
+            0, 1, 0, 0,
+        ]);
+        let mut filled = grid.flood_fill((0, 0), |_, t| *t == 0);
+        filled.sort();
+        assert_eq!(filled, vec![(0, 0), (1, 0)]);
+        assert!(grid.flood_fill((2, 0), |_, t| *t == 0).is_empty());
+        let (labels, count) = grid.label_regions(|_, t| *t == 0);
+        assert_eq!(count, 3);
+        assert_eq!(labels[(0, 0)], labels[(1, 0)]);
+        assert_eq!(labels[(3, 0)], labels[(2, 2)]);
+        assert_ne!(labels[(0, 0)], labels[(0, 2)]);
+        assert_eq!(labels[(1, 1)], None);
+    }
+    #[test]
+    fn test_transforms() {
+        #[rustfmt::skip]
+        let grid = Grid::new(3, 2, vec![
+            1, 2, 3,
+            4, 5, 6,
+        ]);
+        let doubled = grid.map(|_, t| t * 2);
+        assert_eq!(doubled[(2, 1)], 12);
+        assert_eq!(grid.iter_coords().nth(4), Some(((1, 1), &5)));
+        let rect = GridRect {
+            x: 1,
+            y: 0,
+            w: 2,
+            h: 2,
+        };
+        let view = grid.view(rect);
+        assert_eq!(view.get(0, 1), Some(&5));
+        assert_eq!(view.get(2, 0), None);
+        assert_eq!(&*grid.sub_grid(rect).storage, &[2, 3, 5, 6]);
+        let region: Vec<_> = grid
+            .iter_region(GridRect {
+                x: 2,
+                y: 1,
+                w: 5,
+                h: 5,
+            })
+            .collect();
+        assert_eq!(region, vec![((2, 1), &6)]);
+        assert_eq!(&*grid.rotated_cw().storage, &[4, 1, 5, 2, 6, 3]);
+        assert_eq!(&*grid.rotated_ccw().storage, &[3, 6, 2, 5, 1, 4]);
+        assert_eq!(&*grid.flipped_h().storage, &[3, 2, 1, 6, 5, 4]);
+        assert_eq!(&*grid.flipped_v().storage, &[4, 5, 6, 1, 2, 3]);
+        let mut big = grid.resized(4, 3, 0);
+        assert_eq!(&*big.storage, &[1, 2, 3, 0, 4, 5, 6, 0, 0, 0, 0, 0]);
+        big.blit(&grid, (2, 2));
+        assert_eq!(&big.storage[8..], &[0, 0, 1, 2]);
+    }
+    #[test]
     fn test_neighbors() {
         let grid = Grid::new(64, 32, vec![0; 64 * 32]);
         assert_eq!(grid.neighbors_4(0, 0).count(), 2);
@@ -1,15 +1,56 @@
 use frenderer::{
-    sprites::{SheetRegion, Transform},
+    sprites::{Camera2D, SheetRegion, Transform},
     Immediate,
 };
 use crate::geom::*;
+use crate::fov::{self, Fog};
 use crate::grid::{self, Grid};
-use std::collections::HashMap;
+use crate::pathfinding::{self, Connectivity, FlowField};
+use crate::raycast::{self, RayHit};
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
 use std::str::FromStr;
 
+// extra tiles drawn around the camera view when culling
+const RENDER_MARGIN: usize = 1;
+// how dark to draw tiles under fog: ones that were seen before, and ones never seen
+const FOG_REMEMBERED: [u8; 4] = [0, 0, 0, 170];
+const FOG_UNEXPLORED: [u8; 4] = [0, 0, 0, 255];
+// how much grip ice (the i flag) has compared to ordinary ground
+const ICE_FRICTION: f32 = 0.1;
+
+// Index of a legend entry; a level can have up to 65536 different tiles.
+pub type TileId = u16;
+
+// What a level is like past the edges of its grid, set with a `bounds solid|void|wrap` metadata line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutOfBounds {
+    // a wall all the way around, so nothing can leave through a gap in the border
+    #[default]
+    Solid,
+    // open and empty; things can walk off the map and it's up to the game what happens to them
+    Void,
+    // the map repeats, so walking off one edge comes back on the opposite one
+    Wrap,
+}
+
+// What get_tile_at and tiles_within find off the edge of a Solid or Void level.
+const OUTSIDE_SOLID: TileData = TileData {
+    solid: true,
+    friction: 1.0,
+    sheet_region: SheetRegion::ZERO,
+};
+const OUTSIDE_VOID: TileData = TileData {
+    solid: false,
+    friction: 1.0,
+    sheet_region: SheetRegion::ZERO,
+};
+
 #[derive(Clone, Copy, Debug)]
 pub struct TileData {
     pub solid: bool,
+    // how much things moving over this tile get slowed down, 1.0 for ordinary ground (see physics::Kinematic)
+    pub friction: f32,
     pub sheet_region: SheetRegion,
 }
 
@@ -18,17 +59,94 @@ pub struct EntityType {
     name: String,
     strings: Vec<String>,
     numbers: Vec<u16>,
+    // key=value properties, in the order they were written
+    props: Vec<(String, String)>,
 }
 impl EntityType {
     pub fn name(&self) -> &str {
         &self.name
     }
-    // pub fn strings(&self) -> &[String] {
-    //     &self.strings
-    // }
-    // pub fn numbers(&self) -> &[u16] {
-    //     &self.numbers
-    // }
+    pub fn strings(&self) -> &[String] {
+        &self.strings
+    }
+    pub fn numbers(&self) -> &[u16] {
+        &self.numbers
+    }
+    pub fn props(&self) -> &[(String, String)] {
+        &self.props
+    }
+    pub fn has_prop(&self, key: &str) -> bool {
+        self.props.iter().any(|(k, _)| k == key)
+    }
+    // The property `key` parsed as a T, e.g. `let hp: usize = etype.prop("hp")?`.
+    pub fn prop<T: FromStr>(&self, key: &str) -> Result<T, PropError> {
+        parse_prop(&self.props, || format!("{} start", self.name), key)
+    }
+    // Like prop, but a missing property is `default` instead of an error (a mistyped one is still an error).
+    pub fn prop_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, PropError> {
+        or_default(self.prop(key), default)
+    }
+}
+
+// A missing or unparseable key=value property of a start or a level.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PropError {
+    Missing {
+        // what the property would belong to, like `enemy start` or `level level1`
+        owner: String,
+        key: String,
+    },
+    Invalid {
+        owner: String,
+        key: String,
+        value: String,
+        expected: &'static str,
+    },
+}
+impl std::fmt::Display for PropError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PropError::Missing { owner, key } => write!(f, "{owner} has no {key}"),
+            PropError::Invalid {
+                owner,
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "{owner} has {key}={value}, which isn't a valid {expected}"
+            ),
+        }
+    }
+}
+impl std::error::Error for PropError {}
+
+fn parse_prop<T: FromStr>(
+    props: &[(String, String)],
+    owner: impl Fn() -> String,
+    key: &str,
+) -> Result<T, PropError> {
+    let value = props
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+        .ok_or_else(|| PropError::Missing {
+            owner: owner(),
+            key: key.to_string(),
+        })?;
+    value.parse().map_err(|_| PropError::Invalid {
+        owner: owner(),
+        key: key.to_string(),
+        value: value.clone(),
+        expected: std::any::type_name::<T>(),
+    })
+}
+
+fn or_default<T>(result: Result<T, PropError>, default: T) -> Result<T, PropError> {
+    match result {
+        Err(PropError::Missing { .. }) => Ok(default),
+        result => result,
+    }
 }
 
 #[allow(dead_code)]
@@ -36,15 +154,26 @@ pub struct Level {
     name: String,
     sheet: usize,
     bg: SheetRegion,
-    grid: Grid<u8>,
+    grid: Grid<TileId>,
+    // the layout as loaded, so runtime edits can be undone
+    original: Grid<TileId>,
     tileset: Tileset,
-    starts: Vec<(EntityType, Vec2)>,
+    starts: Vec<(EntityType, WorldPos)>,
     tile_size: u16,
+    fog: Option<Fog>,
+    // picks tile variants, so the same level always looks the same
+    seed: u64,
+    // the sheet region each cell is drawn with, which autotiled symbols pick from their neighbors
+    looks: Grid<SheetRegion>,
+    // key=value lines from the metadata section, in the order they were written
+    meta: Vec<(String, String)>,
+    bounds: OutOfBounds,
 }
 
 impl Level {
     /*
-    We'll read from an ad hoc format like this, where FLAGS is either S (solid) or O (open) but could be other stuff later:
+    We'll read from an ad hoc format like this, where FLAGS is either S (solid), O (open) or I (ice, which is open but
+    slippery) but could be other stuff later:
 
     LEVELNAME W H TSZ
     BG X Y W H
@@ -63,10 +192,74 @@ impl Level {
     enemy X Y
     enemy X Y
     enemy X Y
-    door X Y LEVELNAME TO-X TO-Y
+    door X Y to=LEVELNAME to_x=TO-X to_y=TO-Y
     you can add more types of thing if you want
+
+    Anything after a start's X Y written as key=value is a property (no spaces in either half), which games
+    read with EntityType::prop to configure what they spawn there, e.g. `enemy 3 4 kind=bat hp=5 patrol=left`.
+    Other words are kept in order as numbers() if they're numbers and strings() if not.
+
+    The metadata section can also have any number of KEY=VALUE lines, which the engine doesn't look at itself
+    but games read with Level::meta to tune things per level, e.g. `title=The Cellar`, `time_limit=90` or
+    `spawn_rate=0.05`.  The value is the rest of the line, so it can have spaces in it.
+
+    A `bounds solid`, `bounds void` or `bounds wrap` metadata line says what's past the edges of the map
+    (see OutOfBounds); levels are solid all the way around if they don't say.
+
+    A SYM can be any token without whitespace (`.`, `rtl`, `木`), and a legend can have up to 65536 of them.
+
+    A legend entry can be autotiled by putting auto4 (edges only) or auto8 (edges and corners) before its
+    sheet region, then following it with rule lines that pick a different region depending on which neighbors
+    have the same symbol (the edge of the map counts as the same).  A pattern has one character per neighbor,
+    N E S W for auto4 or N NE E SE S SW W NW for auto8, each 1 (same), 0 (different) or x (either).
+    In auto8 a corner only counts as the same if both edges next to it do too.  The first matching rule wins,
+    and cells that match none use the entry's own region:
+
+    w s auto4 255 221 16 16
+    w 0x1x 238 204 16 16
+    w 1x0x 238 272 16 16
+
+    A legend entry can also have variants so big areas of one tile don't look so repetitive.  Each cell picks
+    one at random, weighted, but the same every time for a given cell and level seed (set with a `seed N`
+    metadata line; it's 0 otherwise).  An optional number after the entry's own region is its weight (1 if
+    left out), and each variant line gives a weight and a region.  Variants only change how a tile looks:
+
+    . o 85 17 16 16 6
+    . variant 1 102 17 16 16
+    . variant 1 119 17 16 16
     */
     pub fn from_str(s: &str, sheet: usize, sheet_layer: u16) -> Self {
+        Self::from_str_with_prefabs(s, sheet, sheet_layer, &|name| {
+            panic!("Level stamps prefab {name}, but wasn't given a way to load prefabs")
+        })
+    }
+    /*
+    A start line can also stamp a prefab, another level file holding a reusable piece of map like a room or a shrine:
+
+    stamp X Y PREFABNAME [cw|ccw|180|fliph|flipv]...
+
+    The prefab's map is copied into this one with its top left at X Y, after applying the rotations and flips in order,
+    and its starts are moved along with it and added to this level's.  Symbols this level's legend doesn't have
+    come from the prefab's legend; ones it does have use this level's.  Prefabs can stamp other prefabs (but not
+    themselves, even by way of others), and have to use the same tile size as the level they're stamped into.
+    load_prefab turns a prefab name into its text, e.g. by loading it from an asset cache.
+    */
+    pub fn from_str_with_prefabs(
+        s: &str,
+        sheet: usize,
+        sheet_layer: u16,
+        load_prefab: &dyn Fn(&str) -> String,
+    ) -> Self {
+        Self::from_str_stamping(s, sheet, sheet_layer, load_prefab, &[])
+    }
+    // `stamping` is the prefabs already being loaded on the way to this one, outermost first.
+    fn from_str_stamping(
+        s: &str,
+        sheet: usize,
+        sheet_layer: u16,
+        load_prefab: &dyn Fn(&str) -> String,
+        stamping: &[&str],
+    ) -> Self {
         enum State {
             Metadata,
             Legend,
@@ -89,7 +282,12 @@ impl Level {
         let mut name = None;
         let mut dims = None;
         let mut tsz = 0;
-        let mut legend: HashMap<String, (u8, TileData)> = std::collections::HashMap::new();
+        let mut legend: HashMap<String, (TileId, TileData)> = std::collections::HashMap::new();
+        let mut autotiles: HashMap<String, Autotile> = HashMap::new();
+        let mut variants: HashMap<String, Vec<(u32, SheetRegion)>> = HashMap::new();
+        let mut seed = 0;
+        let mut bounds = OutOfBounds::default();
+        let mut meta: Vec<(String, String)> = vec![];
         let mut grid = vec![];
         let mut starts = vec![];
         let mut bg = SheetRegion::ZERO;
@@ -101,11 +299,36 @@ impl Level {
             } else {
                 match state {
                     State::Metadata => {
+                        if let Some((key, value)) = line.split_once('=') {
+                            let (key, value) = (key.trim(), value.trim());
+                            assert!(
+                                !key.is_empty() && !key.contains(char::is_whitespace),
+                                "Bad metadata key in {line}"
+                            );
+                            assert!(
+                                !meta.iter().any(|(k, _)| k == key),
+                                "Level sets {key} more than once in {line}"
+                            );
+                            meta.push((key.to_string(), value.to_string()));
+                            continue;
+                        }
                         let mut chunks = line.split_whitespace();
                         let md = chunks
                             .next()
                             .expect("No metadata decl string in metadata line {line}");
-                        if md == "bg" {
+                        if md == "seed" {
+                            seed = u64::from_str(
+                                chunks.next().expect("No seed in metadata line {line}"),
+                            )
+                            .expect("Couldn't parse seed as u64 in {line}");
+                        } else if md == "bounds" {
+                            bounds = match chunks.next() {
+                                Some("solid") => OutOfBounds::Solid,
+                                Some("void") => OutOfBounds::Void,
+                                Some("wrap") => OutOfBounds::Wrap,
+                                _ => panic!("bounds should be solid, void or wrap in {line}"),
+                            };
+                        } else if md == "bg" {
                             if bg.w != 0 {
                                 panic!("Two bg entries in metadata");
                             }
@@ -147,35 +370,69 @@ impl Level {
                         }
                     }
                     State::Legend => {
-                        let mut chunks = line.split_whitespace();
+                        let mut chunks = line.split_whitespace().peekable();
                         let sym = chunks.next().expect("Couldn't get tile symbol in {line}");
-                        assert!(!legend.contains_key(sym), "Symbol {sym} already in legend");
                         let flags = chunks
                             .next()
                             .expect("Couldn't get tile flags in {line}")
                             .to_lowercase();
-                        assert!(flags == "o" || flags == "s", "The only valid flags are o(pen) or s(olid), but you could parse other kinds here in {line}");
-                        let x =
-                            u16::from_str(chunks.next().expect("No sheet x in legend line {line}"))
-                                .expect("Couldn't parse sheet x as u16 in {line}");
-                        let y =
-                            u16::from_str(chunks.next().expect("No sheet y in legend line {line}"))
-                                .expect("Couldn't parse sheet y as u16 in {line}");
-                        let w =
-                            i16::from_str(chunks.next().expect("No sheet w in legend line {line}"))
-                                .expect("Couldn't parse sheet w as i16 in {line}");
-                        let h =
-                            i16::from_str(chunks.next().expect("No sheet h in legend line {line}"))
-                                .expect("Couldn't parse sheet h as i16 in {line}");
+                        if flags == "variant" {
+                            let choices = variants.get_mut(sym).unwrap_or_else(|| {
+                                panic!("Variant of {sym} before its legend entry in {line}")
+                            });
+                            let weight =
+                                u32::from_str(chunks.next().expect("No variant weight in {line}"))
+                                    .expect("Couldn't parse variant weight as u32 in {line}");
+                            choices.push((weight, parse_region(&mut chunks, sheet_layer, line)));
+                            continue;
+                        }
+                        if let Some(autotile) = autotiles.get_mut(sym) {
+                            if flags != "o" && flags != "s" && flags != "i" {
+                                let region = parse_region(&mut chunks, sheet_layer, line);
+                                autotile.add_rule(&flags, region, line);
+                                continue;
+                            }
+                        }
+                        assert!(!legend.contains_key(sym), "Symbol {sym} already in legend");
+                        assert!(flags == "o" || flags == "s" || flags == "i", "The only valid flags are o(pen), s(olid) or i(ce), but you could parse other kinds here in {line}");
+                        let kind = match chunks.peek() {
+                            Some(&"auto4") => Some(AutotileKind::Edges),
+                            Some(&"auto8") => Some(AutotileKind::Blob),
+                            _ => None,
+                        };
+                        if let Some(kind) = kind {
+                            chunks.next();
+                            autotiles.insert(
+                                sym.to_string(),
+                                Autotile {
+                                    kind,
+                                    rules: vec![],
+                                },
+                            );
+                        }
                         let data = TileData {
                             solid: flags == "s",
-                            sheet_region: SheetRegion::new(sheet_layer, x, y, 16, w, h),
+                            friction: if flags == "i" { ICE_FRICTION } else { 1.0 },
+                            sheet_region: parse_region(&mut chunks, sheet_layer, line),
                         };
-                        legend.insert(sym.to_string(), (legend.len() as u8, data));
+                        let weight = chunks.next().map_or(1, |w| {
+                            u32::from_str(w).expect("Couldn't parse tile weight as u32 in {line}")
+                        });
+                        variants.insert(sym.to_string(), vec![(weight, data.sheet_region)]);
+                        legend.insert(sym.to_string(), (next_tile_id(&legend, sym), data));
                     }
                     State::Map => {
                         let old_len = grid.len();
-                        grid.extend(line.split_whitespace().map(|sym| legend[sym].0));
+                        grid.extend(line.split_whitespace().map(|sym| {
+                            legend
+                                .get(sym)
+                                .unwrap_or_else(|| {
+                                    panic!(
+                                        "Map uses symbol {sym} which isn't in the legend in {line}"
+                                    )
+                                })
+                                .0
+                        }));
                         assert_eq!(
                             old_len + dims.unwrap().0 as usize,
                             grid.len(),
@@ -193,9 +450,80 @@ impl Level {
                         let y =
                             u16::from_str(chunks.next().expect("No y coord in start line {line}"))
                                 .expect("Couldn't parse y coord as u16 in {line}");
+                        if etype == "stamp" {
+                            let prefab_name = chunks.next().expect("No prefab name in {line}");
+                            let stamping = [stamping, &[prefab_name]].concat();
+                            assert!(
+                                !stamping[..stamping.len() - 1].contains(&prefab_name),
+                                "Prefab {prefab_name} ends up stamping itself: {}",
+                                stamping.join(" -> ")
+                            );
+                            let prefab = Self::from_str_stamping(
+                                &load_prefab(prefab_name),
+                                sheet,
+                                sheet_layer,
+                                load_prefab,
+                                &stamping,
+                            );
+                            assert_eq!(
+                                prefab.tile_size, tsz,
+                                "Prefab {prefab_name} has a different tile size than the level stamping it in {line}"
+                            );
+                            // track where each cell of the prefab came from through the transforms
+                            let mut origins = prefab.grid.map(|c, _| c);
+                            for op in chunks {
+                                origins = match op {
+                                    "cw" => origins.rotated_cw(),
+                                    "ccw" => origins.rotated_ccw(),
+                                    "180" => origins.rotated_cw().rotated_cw(),
+                                    "fliph" => origins.flipped_h(),
+                                    "flipv" => origins.flipped_v(),
+                                    _ => panic!("Unknown prefab transform {op} in {line}"),
+                                };
+                            }
+                            let (w, h) = dims.unwrap();
+                            let (x, y) = (x as usize, y as usize);
+                            assert!(
+                                x + origins.width() <= w as usize
+                                    && y + origins.height() <= h as usize,
+                                "Prefab {prefab_name} doesn't fit in the map at {x}, {y} in {line}"
+                            );
+                            let mut moved = HashMap::new();
+                            for ((px, py), &origin) in origins.iter_coords() {
+                                let tile = prefab.grid[origin] as usize;
+                                let sym = &prefab.tileset.symbols[tile];
+                                if !legend.contains_key(sym) {
+                                    legend.insert(
+                                        sym.clone(),
+                                        (next_tile_id(&legend, sym), prefab.tileset[tile]),
+                                    );
+                                    if let Some(autotile) = &prefab.tileset.autotiles[tile] {
+                                        autotiles.insert(sym.clone(), autotile.clone());
+                                    }
+                                    variants
+                                        .insert(sym.clone(), prefab.tileset.variants[tile].clone());
+                                }
+                                grid[(y + py) * w as usize + x + px] = legend[sym].0;
+                                moved.insert(origin, (x + px, y + py));
+                            }
+                            for (etype, pos) in prefab.starts.iter() {
+                                let (sx, sy) = moved[&prefab.world_to_grid(*pos).into()];
+                                starts.push((etype.clone(), start_pos(sx, sy, tsz, h)));
+                            }
+                            continue;
+                        }
                         let mut strings = vec![];
                         let mut numbers = vec![];
+                        let mut props: Vec<(String, String)> = vec![];
                         for chunk in chunks {
+                            if let Some((key, value)) = chunk.split_once('=') {
+                                assert!(
+                                    !props.iter().any(|(k, _)| k == key),
+                                    "Start sets {key} more than once in {line}"
+                                );
+                                props.push((key.to_string(), value.to_string()));
+                                continue;
+                            }
                             match u16::from_str(chunk) {
                                 Ok(num) => numbers.push(num),
                                 Err(_) => strings.push(chunk.to_string()),
@@ -206,11 +534,9 @@ impl Level {
                                 name: etype.to_string(),
                                 strings,
                                 numbers,
+                                props,
                             },
-                            Vec2 {
-                                x: (x * tsz) as f32 + tsz as f32 / 2.0,
-                                y: ((dims.unwrap().1 - y) * tsz) as f32 - tsz as f32 / 2.0,
-                            },
+                            start_pos(x as usize, y as usize, tsz, dims.unwrap().1),
                         ));
                     }
                     State::Done => {
@@ -225,55 +551,475 @@ impl Level {
         let (w, h) = dims.unwrap();
         assert!(!legend.is_empty());
         assert_eq!(grid.len(), w as usize * h as usize);
-        let mut tiles: Vec<(u8, TileData)> = legend.into_values().collect();
-        tiles.sort_by_key(|(num, _)| *num);
-        Self {
+        let mut tiles: Vec<(TileId, String, TileData)> = legend
+            .into_iter()
+            .map(|(sym, (num, val))| (num, sym, val))
+            .collect();
+        tiles.sort_by_key(|(num, _, _)| *num);
+        let grid = Grid::new(w as usize, h as usize, grid);
+        let looks = grid.map(|_, _| SheetRegion::ZERO);
+        let mut level = Self {
             bg,
             sheet,
             tile_size: tsz,
             name: name.to_string(),
-            grid: Grid::new(w as usize, h as usize, grid),
+            original: grid.clone(),
+            grid,
             tileset: Tileset {
-                tiles: tiles.into_iter().map(|(_num, val)| val).collect(),
+                symbols: tiles.iter().map(|(_num, sym, _val)| sym.clone()).collect(),
+                autotiles: tiles
+                    .iter()
+                    .map(|(_num, sym, _val)| autotiles.remove(sym))
+                    .collect(),
+                variants: tiles
+                    .iter()
+                    .map(|(_num, sym, _val)| variants.remove(sym).unwrap_or_default())
+                    .collect(),
+                tiles: tiles.into_iter().map(|(_num, _sym, val)| val).collect(),
             },
             starts,
+            fog: None,
+            seed,
+            looks,
+            meta,
+            bounds,
+        };
+        level.refresh_looks(level.grid.bounds());
+        level
+    }
+    /*
+    Levels can also be drawn as pixel art, one pixel per tile, with a mapping file that says what each colour means.
+    The mapping file is just like the header of a text level, except the metadata line has no W and H (they come from the image),
+    followed by a colour section and a starts section. Colours are RRGGBB hex; alpha is ignored.
+
+    LEVELNAME TSZ
+    BG X Y W H
+    ====
+    SYM FLAGS X Y W H
+    SYM FLAGS X Y W H
+    ====
+    RRGGBB SYM
+    RRGGBB SYM
+    ====
+    RRGGBB ENTITYTYPE SYM
+    RRGGBB ENTITYTYPE SYM
+
+    A start colour places an entity of that type at the pixel and puts SYM underneath it, e.g. `ff0000 player .`.
+    */
+    pub fn from_image(
+        img: &image::RgbaImage,
+        mapping: &str,
+        sheet: usize,
+        sheet_layer: u16,
+    ) -> Self {
+        let mut sections = vec![vec![]];
+        for line in mapping.lines() {
+            if line.is_empty() {
+                continue;
+            } else if line.chars().all(|c| c == '=') {
+                sections.push(vec![]);
+            } else {
+                sections.last_mut().unwrap().push(line);
+            }
         }
+        assert_eq!(
+            sections.len(),
+            4,
+            "Level image mapping needs metadata, legend, colour and start sections"
+        );
+        let parse_color = |s: &str| {
+            let rgb = u32::from_str_radix(s, 16).expect("Couldn't parse colour {s} as RRGGBB hex");
+            [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]
+        };
+        let mut colors: HashMap<[u8; 3], (Option<&str>, &str)> = HashMap::new();
+        for line in sections[2].iter() {
+            let mut chunks = line.split_whitespace();
+            let rgb = parse_color(chunks.next().expect("No colour in colour line {line}"));
+            let sym = chunks.next().expect("No symbol in colour line {line}");
+            assert!(
+                colors.insert(rgb, (None, sym)).is_none(),
+                "Colour already mapped in {line}"
+            );
+        }
+        for line in sections[3].iter() {
+            let mut chunks = line.split_whitespace();
+            let rgb = parse_color(chunks.next().expect("No colour in start line {line}"));
+            let etype = chunks.next().expect("No entity type in start line {line}");
+            let sym = chunks
+                .next()
+                .expect("No underlying symbol in start line {line}");
+            assert!(
+                colors.insert(rgb, (Some(etype), sym)).is_none(),
+                "Colour already mapped in {line}"
+            );
+        }
+        let mut header = String::new();
+        for line in sections[0]
+            .iter()
+            .chain(["===="].iter())
+            .chain(sections[1].iter())
+        {
+            header.push_str(line);
+            header.push('\n');
+        }
+        let (w, h) = (img.width() as usize, img.height() as usize);
+        let mut symbols = Vec::with_capacity(w * h);
+        let mut starts = vec![];
+        for (y, row) in img.rows().enumerate() {
+            for (x, px) in row.enumerate() {
+                let rgb = [px[0], px[1], px[2]];
+                let (etype, sym) = colors
+                    .get(&rgb)
+                    .unwrap_or_else(|| panic!("Unmapped colour {rgb:?} at pixel {x}, {y}"));
+                if let Some(etype) = etype {
+                    starts.push((*etype, (x, y)));
+                }
+                symbols.push(*sym);
+            }
+        }
+        Self::from_symbols(
+            &header,
+            &Grid::new(w, h, symbols),
+            &starts,
+            sheet,
+            sheet_layer,
+        )
+    }
+    /*
+    Generated levels are built from a grid of symbols plus a list of starts at grid coordinates.
+    The header is the metadata and legend sections of a level, written like an image mapping's (no W and H):
+
+    LEVELNAME TSZ
+    BG X Y W H
+    ====
+    SYM FLAGS X Y W H
+
+    A start is an entity type, optionally followed by its extra arguments, e.g. `("door to=level2 to_x=4 to_y=5", (x, y))`.
+    */
+    pub fn from_symbols<S: AsRef<str>>(
+        header: &str,
+        symbols: &Grid<S>,
+        starts: &[(&str, grid::Coord)],
+        sheet: usize,
+        sheet_layer: u16,
+    ) -> Self {
+        // Rather than building a Level by hand, write out the equivalent text level so every format goes through one parser.
+        let mut text = String::new();
+        let mut name_line = true;
+        for line in header.lines() {
+            if line.is_empty() {
+                continue;
+            }
+            let mut chunks = line.split_whitespace();
+            let name = chunks.next().unwrap_or_default();
+            // the first line names the level; the rest (bg, seed, key=value, the legend) go through as they are
+            if std::mem::take(&mut name_line) {
+                let tsz = chunks.next().expect("No tile size in metadata line {line}");
+                text.push_str(&format!(
+                    "{name} {} {} {tsz}",
+                    symbols.width(),
+                    symbols.height()
+                ));
+            } else {
+                text.push_str(line);
+            }
+            text.push('\n');
+        }
+        text.push_str("====\n");
+        for row in symbols.row_iter() {
+            for sym in row {
+                text.push_str(sym.as_ref());
+                text.push(' ');
+            }
+            text.push('\n');
+        }
+        text.push_str("====\n");
+        for (etype, (x, y)) in starts {
+            let (etype, args) = etype.split_once(' ').unwrap_or((etype, ""));
+            text.push_str(&format!("{etype} {x} {y} {args}\n"));
+        }
+        Self::from_str(&text, sheet, sheet_layer)
+    }
+    /*
+    Levels can also be compiled to a compact binary form that loads without any parsing, e.g. for big generated
+    maps or for embedding in the wasm build (see the compile_level binary, which converts text levels).
+    It holds everything from_str would have read, with prefabs already stamped in, and the current tiles
+    (so a level edited with set_tile comes back edited, and reset_tiles goes back to that).
+    Numbers are little-endian and strings and lists are prefixed with a u32 length:
+
+    b"LVLB" VERSION:u16
+    NAME TSZ:u16 W:u16 H:u16 SEED:u64 BOUNDS:u8 (0 solid, 1 void, 2 wrap) BG METACOUNT, then for each: KEY VALUE
+    TILECOUNT, then for each: SYM SOLID:u8 FRICTION:f32 REGION AUTOKIND:u8 (0 none, 1 auto4, 2 auto8) RULES VARIANTS
+    W*H tile ids:u16, row by row from the top
+    STARTCOUNT, then for each: TYPE STRINGS NUMBERS PROPS X:f32 Y:f32
+
+    A region is X:u16 Y:u16 W:i16 H:i16 DEPTH:u16; its sheet layer comes from from_bytes.
+    The version goes up whenever the layout changes, and older versions are refused rather than misread.
+    */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer(vec![]);
+        out.0.extend_from_slice(BINARY_MAGIC);
+        out.u16(BINARY_VERSION);
+        out.str(&self.name);
+        out.u16(self.tile_size);
+        out.u16(self.grid.width() as u16);
+        out.u16(self.grid.height() as u16);
+        out.0.extend_from_slice(&self.seed.to_le_bytes());
+        out.0.push(match self.bounds {
+            OutOfBounds::Solid => 0,
+            OutOfBounds::Void => 1,
+            OutOfBounds::Wrap => 2,
+        });
+        out.region(self.bg);
+        out.len(self.meta.len());
+        for (key, value) in self.meta.iter() {
+            out.str(key);
+            out.str(value);
+        }
+        out.len(self.tileset.tiles.len());
+        for (idx, tile) in self.tileset.tiles.iter().enumerate() {
+            out.str(&self.tileset.symbols[idx]);
+            out.0.push(tile.solid as u8);
+            out.0.extend_from_slice(&tile.friction.to_le_bytes());
+            out.region(tile.sheet_region);
+            match &self.tileset.autotiles[idx] {
+                None => out.0.push(0),
+                Some(autotile) => {
+                    out.0.push(match autotile.kind {
+                        AutotileKind::Edges => 1,
+                        AutotileKind::Blob => 2,
+                    });
+                    out.len(autotile.rules.len());
+                    for (care, want, region) in autotile.rules.iter() {
+                        out.0.extend_from_slice(&[*care, *want]);
+                        out.region(*region);
+                    }
+                }
+            }
+            out.len(self.tileset.variants[idx].len());
+            for (weight, region) in self.tileset.variants[idx].iter() {
+                out.0.extend_from_slice(&weight.to_le_bytes());
+                out.region(*region);
+            }
+        }
+        for idx in 0..self.grid.width() * self.grid.height() {
+            out.u16(self.grid[idx]);
+        }
+        out.len(self.starts.len());
+        for (etype, pos) in self.starts.iter() {
+            out.str(&etype.name);
+            out.len(etype.strings.len());
+            for string in etype.strings.iter() {
+                out.str(string);
+            }
+            out.len(etype.numbers.len());
+            for num in etype.numbers.iter() {
+                out.u16(*num);
+            }
+            out.len(etype.props.len());
+            for (key, value) in etype.props.iter() {
+                out.str(key);
+                out.str(value);
+            }
+            out.0.extend_from_slice(&pos.x.to_le_bytes());
+            out.0.extend_from_slice(&pos.y.to_le_bytes());
+        }
+        out.0
+    }
+    // Reads a level written by to_bytes.  Like from_str, this panics if the data is malformed.
+    pub fn from_bytes(bytes: &[u8], sheet: usize, sheet_layer: u16) -> Self {
+        let mut input = Reader { bytes, sheet_layer };
+        assert!(
+            input.take(BINARY_MAGIC.len()) == BINARY_MAGIC,
+            "Not a compiled level"
+        );
+        let version = input.u16();
+        assert!(
+            version == BINARY_VERSION,
+            "Compiled level is version {version} but this engine reads version {BINARY_VERSION}; recompile it"
+        );
+        let name = input.str();
+        let tile_size = input.u16();
+        let (w, h) = (input.u16() as usize, input.u16() as usize);
+        let seed = u64::from_le_bytes(input.take(8).try_into().unwrap());
+        let bounds = match input.take(1)[0] {
+            0 => OutOfBounds::Solid,
+            1 => OutOfBounds::Void,
+            2 => OutOfBounds::Wrap,
+            other => panic!("Unknown bounds {other} in compiled level"),
+        };
+        let bg = input.region();
+        let meta = (0..input.len())
+            .map(|_| (input.str(), input.str()))
+            .collect();
+        let mut tileset = Tileset {
+            tiles: vec![],
+            symbols: vec![],
+            autotiles: vec![],
+            variants: vec![],
+        };
+        for _tile in 0..input.len() {
+            tileset.symbols.push(input.str());
+            tileset.tiles.push(TileData {
+                solid: input.take(1)[0] != 0,
+                friction: f32::from_le_bytes(input.take(4).try_into().unwrap()),
+                sheet_region: input.region(),
+            });
+            let kind = match input.take(1)[0] {
+                0 => None,
+                1 => Some(AutotileKind::Edges),
+                2 => Some(AutotileKind::Blob),
+                other => panic!("Unknown autotile kind {other} in compiled level"),
+            };
+            tileset.autotiles.push(kind.map(|kind| {
+                Autotile {
+                    kind,
+                    rules: (0..input.len())
+                        .map(|_| {
+                            let pattern = input.take(2);
+                            (pattern[0], pattern[1], input.region())
+                        })
+                        .collect(),
+                }
+            }));
+            let variants = (0..input.len())
+                .map(|_| {
+                    (
+                        u32::from_le_bytes(input.take(4).try_into().unwrap()),
+                        input.region(),
+                    )
+                })
+                .collect();
+            tileset.variants.push(variants);
+        }
+        let grid = Grid::new(w, h, (0..w * h).map(|_| input.u16()).collect::<Vec<_>>());
+        assert!(
+            (0..w * h).all(|idx| (grid[idx] as usize) < tileset.tiles.len()),
+            "Compiled level's map uses a tile that isn't in its legend"
+        );
+        let mut starts = vec![];
+        for _start in 0..input.len() {
+            let name = input.str();
+            let strings = (0..input.len()).map(|_| input.str()).collect();
+            let numbers = (0..input.len()).map(|_| input.u16()).collect();
+            let props = (0..input.len())
+                .map(|_| (input.str(), input.str()))
+                .collect();
+            let pos = WorldPos {
+                x: f32::from_le_bytes(input.take(4).try_into().unwrap()),
+                y: f32::from_le_bytes(input.take(4).try_into().unwrap()),
+            };
+            starts.push((
+                EntityType {
+                    name,
+                    strings,
+                    numbers,
+                    props,
+                },
+                pos,
+            ));
+        }
+        assert!(input.bytes.is_empty(), "Extra data after compiled level");
+        let looks = grid.map(|_, _| SheetRegion::ZERO);
+        let mut level = Self {
+            bg,
+            sheet,
+            tile_size,
+            name,
+            original: grid.clone(),
+            grid,
+            tileset,
+            starts,
+            fog: None,
+            seed,
+            looks,
+            meta,
+            bounds,
+        };
+        level.refresh_looks(level.grid.bounds());
+        level
     }
     pub fn sprite_count(&self) -> usize {
         self.grid.width() * self.grid.height() + 1
     }
+    // The columns and rows of the grid that overlap the camera's view, grown by `margin` tiles on each side
+    // so sprites don't pop in at the edges while the camera moves.
+    pub fn visible_range(&self, camera: &Camera2D, margin: usize) -> (Range<usize>, Range<usize>) {
+        let tsz = self.tile_size as f32;
+        let w = self.grid.width();
+        let h = self.grid.height();
+        let left = (camera.screen_pos[0] / tsz).floor().max(0.0) as usize;
+        let right = ((camera.screen_pos[0] + camera.screen_size[0]) / tsz)
+            .ceil()
+            .max(0.0) as usize;
+        // camera y goes up from the bottom of the map, but grid rows go down from the top
+        let bottom = (camera.screen_pos[1] / tsz).floor().max(0.0) as usize;
+        let top = ((camera.screen_pos[1] + camera.screen_size[1]) / tsz)
+            .ceil()
+            .max(0.0) as usize;
+        let cols = left.saturating_sub(margin).min(w)..(right + margin).min(w);
+        let rows = h.saturating_sub(top + margin)..h.saturating_sub(bottom.saturating_sub(margin));
+        (cols, rows)
+    }
+    // How many sprites render_visible will use for a camera of this size, wherever it is pointed.
+    pub fn sprite_count_for_view(&self, camera: &Camera2D) -> usize {
+        let tsz = self.tile_size as f32;
+        let cols = (camera.screen_size[0] / tsz).ceil() as usize + 1 + 2 * RENDER_MARGIN;
+        let rows = (camera.screen_size[1] / tsz).ceil() as usize + 1 + 2 * RENDER_MARGIN;
+        if self.bounds == OutOfBounds::Wrap {
+            // the map repeats, so the view can be bigger than it
+            return cols * rows;
+        }
+        cols.min(self.grid.width()) * rows.min(self.grid.height()) + 1
+    }
     pub fn render_immediate(&self, frend: &mut Immediate) -> usize {
         let len = self.sprite_count();
         let (trfs, uvs) = frend.draw_sprites(self.sheet, len);
         self.render_into(trfs, uvs)
     }
+    // Like render_immediate, but only draws the tiles the camera can see.
+    // Immediate grows the sprite group as needed, so callers don't have to size it for the whole map.
+    pub fn render_visible(&self, frend: &mut Immediate, camera: &Camera2D) -> usize {
+        if self.bounds == OutOfBounds::Wrap {
+            return self.render_wrapped(frend, camera);
+        }
+        let (cols, rows) = self.visible_range(camera, RENDER_MARGIN);
+        let len = cols.len() * rows.len() + 1;
+        let (trfs, uvs) = frend.draw_sprites(self.sheet, len);
+        self.render_region_into(trfs, uvs, cols, rows)
+    }
     pub fn render_into(&self, trfs: &mut [Transform], uvs: &mut [SheetRegion]) -> usize {
-        let w = self.grid.width();
+        self.render_region_into(trfs, uvs, 0..self.grid.width(), 0..self.grid.height())
+    }
+    pub fn render_region_into(
+        &self,
+        trfs: &mut [Transform],
+        uvs: &mut [SheetRegion],
+        cols: Range<usize>,
+        rows: Range<usize>,
+    ) -> usize {
+        let w = cols.len();
         let h = self.grid.height();
         assert_eq!(trfs.len(), uvs.len());
-        assert_eq!(trfs.len(), w * h + 1);
-        for ((y, row), (trfs, uvs)) in self
-            .grid
-            .row_iter()
-            .enumerate()
-            .zip(trfs.chunks_exact_mut(w).zip(uvs.chunks_exact_mut(w)))
-        {
-            for ((x, tile), (trf, uv)) in row
-                .iter()
-                .enumerate()
-                .zip(trfs.iter_mut().zip(uvs.iter_mut()))
+        assert_eq!(trfs.len(), w * rows.len() + 1);
+        if w > 0 {
+            for (y, (trfs, uvs)) in rows
+                .clone()
+                .zip(trfs.chunks_exact_mut(w).zip(uvs.chunks_exact_mut(w)))
             {
-                // NOTE: we're converting from grid coordinates to "sprite center coordinates", so we have to flip y...
-                let y = h - y - 1;
-                *trf = Transform {
-                    // and multiply by tile sz *and* offset by half tile sz
-                    x: (x * self.tile_size as usize + self.tile_size as usize / 2) as f32,
-                    y: (y * self.tile_size as usize + self.tile_size as usize / 2) as f32,
-                    w: self.tile_size,
-                    h: self.tile_size,
-                    rot: 0.0,
-                };
-                *uv = self.tileset[*tile as usize].sheet_region;
+                for (x, (trf, uv)) in cols.clone().zip(trfs.iter_mut().zip(uvs.iter_mut())) {
+                    // NOTE: we're converting from grid coordinates to "sprite center coordinates", so we have to flip y...
+                    let y = h - y - 1;
+                    *trf = Transform {
+                        // and multiply by tile sz *and* offset by half tile sz
+                        x: (x * self.tile_size as usize + self.tile_size as usize / 2) as f32,
+                        y: (y * self.tile_size as usize + self.tile_size as usize / 2) as f32,
+                        w: self.tile_size,
+                        h: self.tile_size,
+                        rot: 0.0,
+                    };
+                    *uv = self.look((x, h - y - 1));
+                }
             }
         }
         if self.bg.w != 0 {
@@ -286,64 +1032,557 @@ impl Level {
             };
             uvs[uvs.len() - 1] = self.bg;
         }
-        w * h + 1
+        w * rows.len() + 1
+    }
+    // Draws a wrapped level around the camera, repeating the map wherever the view hangs off an edge.
+    // The bg isn't drawn, since it only covers one copy of the map.
+    fn render_wrapped(&self, frend: &mut Immediate, camera: &Camera2D) -> usize {
+        let tsz = self.tile_size as f32;
+        let margin = RENDER_MARGIN as isize;
+        let h = self.grid.height() as isize;
+        let left = (camera.screen_pos[0] / tsz).floor() as isize - margin;
+        let right = ((camera.screen_pos[0] + camera.screen_size[0]) / tsz).ceil() as isize + margin;
+        let bottom = (camera.screen_pos[1] / tsz).floor() as isize - margin;
+        let top = ((camera.screen_pos[1] + camera.screen_size[1]) / tsz).ceil() as isize + margin;
+        let len = ((right - left) * (top - bottom)) as usize;
+        let (trfs, uvs) = frend.draw_sprites(self.sheet, len);
+        // `up` counts rows up from the bottom of the map like world y does, rather than down like the grid
+        let cells = (bottom..top).flat_map(|up| (left..right).map(move |col| (col, up)));
+        for ((trf, uv), (col, up)) in trfs.iter_mut().zip(uvs.iter_mut()).zip(cells) {
+            *trf = Transform {
+                x: (col as f32 + 0.5) * tsz,
+                y: (up as f32 + 0.5) * tsz,
+                w: self.tile_size,
+                h: self.tile_size,
+                rot: 0.0,
+            };
+            *uv = self.look(self.wrap_cell((col, h - up - 1)).unwrap());
+        }
+        len
+    }
+    // How a cell should be drawn right now, darkened if it's under fog.
+    fn look(&self, coord: grid::Coord) -> SheetRegion {
+        let uv = self.looks[coord];
+        match &self.fog {
+            Some(fog) if !fog.is_explored(coord) => uv.with_colormod(FOG_UNEXPLORED),
+            Some(fog) if !fog.is_visible(coord) => uv.with_colormod(FOG_REMEMBERED),
+            _ => uv,
+        }
     }
     #[allow(dead_code)]
     pub fn name(&self) -> &str {
         &self.name
     }
-    pub fn starts(&self) -> &[(EntityType, Vec2)] {
+    pub fn starts(&self) -> &[(EntityType, WorldPos)] {
         &self.starts
     }
-    pub fn get_tile_at(&self, pos: Vec2) -> Option<&TileData> {
-        let (gx, gy) = self.world_to_grid(pos);
-        self.grid.get(gx, gy).map(|t| &self.tileset[*t as usize])
+    // The tile at pos, or off the edge of the map, whatever the level's bounds say is out there.
+    pub fn get_tile_at(&self, pos: WorldPos) -> &TileData {
+        match self.cell_at(pos) {
+            Some(GridPos { x, y }) => &self.tileset[self.grid[(x, y)] as usize],
+            None => self.outside(),
+        }
+    }
+    fn outside(&self) -> &'static TileData {
+        match self.bounds {
+            OutOfBounds::Void => &OUTSIDE_VOID,
+            // wrapped levels never look outside, but treat it as a wall to be safe
+            OutOfBounds::Solid | OutOfBounds::Wrap => &OUTSIDE_SOLID,
+        }
+    }
+    pub fn get_tile(&self, coord: GridPos) -> Option<&TileData> {
+        self.grid
+            .get(coord.x, coord.y)
+            .map(|t| &self.tileset[*t as usize])
+    }
+    pub fn tile_symbol(&self, coord: GridPos) -> Option<&str> {
+        self.grid
+            .get(coord.x, coord.y)
+            .map(|t| self.tileset.symbols[*t as usize].as_str())
+    }
+    pub fn tile_symbol_at(&self, pos: WorldPos) -> Option<&str> {
+        self.tile_symbol(self.cell_at(pos)?)
+    }
+    // Tiles are looked up from the grid every time we collide or render, so a change here
+    // takes effect right away. Returns false if coord is outside the level.
+    pub fn set_tile(&mut self, coord: GridPos, sym: &str) -> bool {
+        let tile = self
+            .tileset
+            .symbol_index(sym)
+            .unwrap_or_else(|| panic!("Symbol {sym} not in legend of level {}", self.name));
+        match self.grid.get_mut(coord.x, coord.y) {
+            Some(t) => {
+                *t = tile;
+                // neighbors may need a different autotile now too
+                self.refresh_looks(grid::GridRect {
+                    x: coord.x.saturating_sub(1),
+                    y: coord.y.saturating_sub(1),
+                    w: 3,
+                    h: 3,
+                });
+                true
+            }
+            None => false,
+        }
+    }
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+    pub fn bounds(&self) -> OutOfBounds {
+        self.bounds
+    }
+    pub fn metadata(&self) -> &[(String, String)] {
+        &self.meta
+    }
+    pub fn has_meta(&self, key: &str) -> bool {
+        self.meta.iter().any(|(k, _)| k == key)
+    }
+    // The metadata property `key` parsed as a T, e.g. `let par: u32 = level.meta("par")?`.
+    pub fn meta<T: FromStr>(&self, key: &str) -> Result<T, PropError> {
+        parse_prop(&self.meta, || format!("level {}", self.name), key)
+    }
+    // Like meta, but a missing property is `default` instead of an error (a mistyped one is still an error).
+    pub fn meta_or<T: FromStr>(&self, key: &str, default: T) -> Result<T, PropError> {
+        or_default(self.meta(key), default)
+    }
+    // The sheet region a cell is drawn with, after autotiling and picking variants.
+    pub fn tile_region(&self, coord: GridPos) -> Option<SheetRegion> {
+        self.looks.get(coord.x, coord.y).copied()
+    }
+    fn refresh_looks(&mut self, area: grid::GridRect) {
+        for c in area.intersect(self.grid.bounds()).coords() {
+            let tile = self.grid[c] as usize;
+            let base = self.tileset.variant(tile, self.seed, c);
+            self.looks[c] = match &self.tileset.autotiles[tile] {
+                Some(autotile) => {
+                    let same = |dx: isize, dy: isize| {
+                        let (x, y) = (c.0 as isize + dx, c.1 as isize + dy);
+                        if x < 0 || y < 0 {
+                            return true;
+                        }
+                        self.grid
+                            .get(x as usize, y as usize)
+                            .is_none_or(|t| *t as usize == tile)
+                    };
+                    autotile.region(same).unwrap_or(base)
+                }
+                None => base,
+            };
+        }
+    }
+    pub fn set_tile_at(&mut self, pos: WorldPos, sym: &str) -> bool {
+        match self.cell_at(pos) {
+            Some(coord) => self.set_tile(coord, sym),
+            None => false,
+        }
+    }
+    pub fn has_symbol(&self, sym: &str) -> bool {
+        self.tileset.symbol_index(sym).is_some()
+    }
+    pub fn is_modified(&self, coord: GridPos) -> bool {
+        self.grid.get(coord.x, coord.y) != self.original.get(coord.x, coord.y)
+    }
+    pub fn reset_tiles(&mut self) {
+        self.grid = self.original.clone();
+        self.refresh_looks(self.grid.bounds());
     }
     // pub fn tile_index_at(&self, pos: Vec2) -> Option<usize> {
     //     let (gx, gy) = self.world_to_grid(pos);
     //     self.grid.xy_to_index(gx, gy)
     // }
-    pub fn grid_to_world(&self, pos: grid::Coord) -> Vec2 {
-        Vec2 {
-            x: pos.0 as f32 * self.tile_size as f32,
-            y: (self.grid.height() - pos.1 - 1) as f32 * self.tile_size as f32,
+    // The bottom left corner of a cell.
+    pub fn grid_to_world(&self, pos: GridPos) -> WorldPos {
+        WorldPos {
+            x: pos.x as f32 * self.tile_size as f32,
+            y: (self.grid.height() - pos.y - 1) as f32 * self.tile_size as f32,
+        }
+    }
+    // The middle of a cell, where starts and path waypoints are.
+    pub fn tile_center(&self, pos: GridPos) -> WorldPos {
+        let half = self.tile_size as f32 / 2.0;
+        self.grid_to_world(pos) + Vec2 { x: half, y: half }
+    }
+    // The cell pos is in.  On a wrapped level that's wherever pos wraps around to; otherwise positions
+    // off the map give the nearest cell along the left and top edges and a cell past the grid (which
+    // lookups treat as missing) along the right and bottom.  Use cell_at to tell when pos is off the map.
+    pub fn world_to_grid(&self, pos: WorldPos) -> GridPos {
+        let (x, y) = self.unwrapped_cell(pos);
+        match self.wrap_cell((x, y)) {
+            Some(coord) => coord.into(),
+            None => GridPos {
+                x: x.max(0) as usize,
+                y: y.max(0) as usize,
+            },
         }
     }
-    pub fn world_to_grid(&self, pos: Vec2) -> grid::Coord {
+    // The cell pos is in, after wrapping if the level wraps, or None if pos is off the map.
+    pub fn cell_at(&self, pos: WorldPos) -> Option<GridPos> {
+        self.wrap_cell(self.unwrapped_cell(pos)).map(GridPos::from)
+    }
+    // Column and row of pos as if the grid went on forever in every direction.
+    // A cell covers its bottom left corner up to (but not including) the next cell's.
+    fn unwrapped_cell(&self, pos: WorldPos) -> (isize, isize) {
+        let tsz = self.tile_size as f32;
         (
-            (pos.x / self.tile_size as f32) as usize,
-            (((self.grid.height() as f32 * self.tile_size as f32) - pos.y - 1.0)
-                / self.tile_size as f32) as usize,
+            (pos.x / tsz).floor() as isize,
+            self.grid.height() as isize - 1 - (pos.y / tsz).floor() as isize,
         )
     }
-    pub fn tiles_within(&self, rect: Rect) -> impl Iterator<Item = (usize, Rect, &TileData)> {
-        let (l, t) = self.world_to_grid(Vec2 {
+    fn wrap_cell(&self, (x, y): (isize, isize)) -> Option<grid::Coord> {
+        let (w, h) = (self.grid.width() as isize, self.grid.height() as isize);
+        if self.bounds == OutOfBounds::Wrap {
+            Some((x.rem_euclid(w) as usize, y.rem_euclid(h) as usize))
+        } else if (0..w).contains(&x) && (0..h).contains(&y) {
+            Some((x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+    // On a wrapped level, brings a position that has gone off the map back on from the other side.
+    // Other levels leave it as it is.  Call this after moving anything that can cross an edge.
+    pub fn wrap_pos(&self, pos: WorldPos) -> WorldPos {
+        if self.bounds != OutOfBounds::Wrap {
+            return pos;
+        }
+        let tsz = self.tile_size as f32;
+        WorldPos {
+            x: pos.x.rem_euclid(self.grid.width() as f32 * tsz),
+            y: pos.y.rem_euclid(self.grid.height() as f32 * tsz),
+        }
+    }
+    // The way from one position to another: just `to - from`, except on a wrapped level it's the shortest
+    // way, which might go across an edge.  Use it to aim or measure distances between things.
+    pub fn offset(&self, from: WorldPos, to: WorldPos) -> Vec2 {
+        let mut d = to - from;
+        if self.bounds == OutOfBounds::Wrap {
+            let tsz = self.tile_size as f32;
+            let (w, h) = (
+                self.grid.width() as f32 * tsz,
+                self.grid.height() as f32 * tsz,
+            );
+            d.x -= w * (d.x / w).round();
+            d.y -= h * (d.y / h).round();
+        }
+        d
+    }
+    // The tiles overlapping rect, plus a ring of one tile around it, with their rects in world space and
+    // their grid coordinates.  Off the map these follow the level's bounds: a Solid level has solid tiles
+    // out there (with no coordinate), a Void one has none, and a wrapped one has the tiles from the other
+    // side of the map, placed next to rect so collisions across an edge just work.
+    pub fn tiles_within(
+        &self,
+        rect: Rect,
+    ) -> impl Iterator<Item = (Option<GridPos>, Rect, &TileData)> {
+        let (l, t) = self.unwrapped_cell(WorldPos {
             x: rect.x,
             y: rect.y,
         });
-        let (r, b) = self.world_to_grid(Vec2 {
+        let (r, b) = self.unwrapped_cell(WorldPos {
             x: rect.x + rect.w as f32,
             y: rect.y + rect.h as f32,
         });
-        ((b.max(1) - 1)..(t + 2)).flat_map(move |row| {
-            ((l.max(1) - 1)..(r + 2)).filter_map(move |col| {
-                self.grid.get(col, row).map(|tile_dat| {
-                    let tid = self.grid.coord_to_index((col, row)).unwrap();
-                    (
-                        tid,
-                        self.tile_rect_for_coord((col, row)),
-                        &self.tileset[*tile_dat as usize],
-                    )
-                })
+        let tsz = self.tile_size;
+        let h = self.grid.height() as isize;
+        ((b - 1)..(t + 2)).flat_map(move |row| {
+            ((l - 1)..(r + 2)).filter_map(move |col| {
+                let rect = Rect {
+                    x: col as f32 * tsz as f32,
+                    y: (h - row - 1) as f32 * tsz as f32,
+                    w: tsz,
+                    h: tsz,
+                };
+                match self.wrap_cell((col, row)) {
+                    Some(coord) => Some((
+                        Some(coord.into()),
+                        rect,
+                        &self.tileset[self.grid[coord] as usize],
+                    )),
+                    None if self.bounds == OutOfBounds::Solid => Some((None, rect, &OUTSIDE_SOLID)),
+                    None => None,
+                }
             })
         })
     }
+    // Where a rect moving by delta first runs into a solid tile (including the outside of a solid-bounded level),
+    // however far it moves.  Faces shared by two solid tiles are skipped, so something sliding along a wall
+    // doesn't catch on the seams between its tiles.
+    pub fn sweep(&self, rect: Rect, delta: Vec2) -> Option<SweepHit> {
+        // the area it passes through, cut off at the biggest Rect there is but measured from where it starts,
+        // so a move too long to fit still finds the walls nearest to it
+        let span = |len: u16, d: f32| (len as f32 + d.abs().ceil()).min(u16::MAX as f32);
+        let (w, h) = (span(rect.w, delta.x), span(rect.h, delta.y));
+        let swept = Rect {
+            x: if delta.x < 0.0 {
+                rect.x + rect.w as f32 - w
+            } else {
+                rect.x
+            },
+            y: if delta.y < 0.0 {
+                rect.y + rect.h as f32 - h
+            } else {
+                rect.y
+            },
+            w: w as u16,
+            h: h as u16,
+        };
+        let tsz = self.tile_size as f32;
+        self.tiles_within(swept)
+            .filter(|(_, _, tile)| tile.solid)
+            .filter_map(|(_, tile, _)| rect.sweep(delta, tile).map(|hit| (tile, hit)))
+            .filter(|(tile, hit)| {
+                let beyond = WorldPos {
+                    x: tile.x + tsz / 2.0 + hit.normal.x * tsz,
+                    y: tile.y + tsz / 2.0 + hit.normal.y * tsz,
+                };
+                !self.get_tile_at(beyond).solid
+            })
+            .map(|(_, hit)| hit)
+            .min_by(|a, b| a.time.total_cmp(&b.time))
+    }
+    // Shortest path between two grid cells that only walks on non-solid tiles.
+    pub fn find_path(
+        &self,
+        start: GridPos,
+        goal: GridPos,
+        conn: Connectivity,
+    ) -> Option<Vec<GridPos>> {
+        self.find_path_with(
+            start,
+            goal,
+            conn,
+            |_, tile| {
+                if tile.solid {
+                    None
+                } else {
+                    Some(1.0)
+                }
+            },
+        )
+    }
+    // Like find_path, but `cost` decides how expensive each tile is to walk onto (None for impassable).
+    pub fn find_path_with(
+        &self,
+        start: GridPos,
+        goal: GridPos,
+        conn: Connectivity,
+        cost: impl Fn(GridPos, &TileData) -> Option<f32>,
+    ) -> Option<Vec<GridPos>> {
+        let cost = |c: grid::Coord, t: &TileId| cost(c.into(), &self.tileset[*t as usize]);
+        let (start, goal) = (start.into(), goal.into());
+        // paths on a wrapped level can take a shortcut across an edge
+        let path = if self.bounds == OutOfBounds::Wrap {
+            pathfinding::find_path_wrapping(&self.grid, start, goal, conn, cost)
+        } else {
+            pathfinding::find_path(&self.grid, start, goal, conn, cost)
+        }?;
+        Some(path.into_iter().map(GridPos::from).collect())
+    }
+    // A flow field over this level's non-solid tiles, with no goals yet; see FlowField::set_goals.
+    pub fn flow_field(&self, conn: Connectivity) -> FlowField {
+        if self.bounds == OutOfBounds::Wrap {
+            FlowField::new_wrapping(&self.grid, conn, |_, t| self.open_cost(*t))
+        } else {
+            FlowField::new(&self.grid, conn, |_, t| self.open_cost(*t))
+        }
+    }
+    // Points the field at new goal cells (e.g. wherever the things it leads to are now).
+    pub fn set_flow_goals(&self, field: &mut FlowField, goals: &[GridPos]) {
+        let goals: Vec<grid::Coord> = goals.iter().map(|&g| g.into()).collect();
+        field.set_goals(&goals);
+    }
+    // Call after set_tile so the field walks around (or through) the changed tiles.
+    pub fn refresh_flow_field(&self, field: &mut FlowField) {
+        field.update_costs(&self.grid, |_, t| self.open_cost(*t));
+    }
+    fn open_cost(&self, tile: TileId) -> Option<f32> {
+        if self.tileset[tile as usize].solid {
+            None
+        } else {
+            Some(1.0)
+        }
+    }
+    // Unit vector pointing from pos toward the center of the next tile in the field,
+    // or None if pos is already on a goal tile or can't reach one.
+    pub fn flow_direction(&self, field: &FlowField, pos: WorldPos) -> Option<Vec2> {
+        let next = field.next_cell(self.world_to_grid(pos).into())?;
+        let heading = self.offset(pos, self.tile_center(next.into()));
+        if heading.mag_sq() > 0.0 {
+            Some(heading.normalize())
+        } else {
+            None
+        }
+    }
+    // Casts a ray through the level's solid tiles.  Everything here is in world space, so the hit's
+    // distance is in pixels and its normal points up for the top face of a tile.
+    pub fn raycast(&self, origin: WorldPos, dir: Vec2, max_dist: f32) -> Option<RayHit> {
+        let tsz = self.tile_size as f32;
+        let h = self.grid.height() as f32;
+        // grid rows go down, so flip y on the way in and on the way out
+        let hit = raycast::cast(
+            &self.grid,
+            Vec2 {
+                x: origin.x / tsz,
+                y: h - origin.y / tsz,
+            },
+            Vec2 {
+                x: dir.x,
+                y: -dir.y,
+            },
+            max_dist / tsz,
+            |_, t| self.tileset[*t as usize].solid,
+        )?;
+        Some(RayHit {
+            cell: hit.cell,
+            distance: hit.distance * tsz,
+            point: Vec2 {
+                x: hit.point.x * tsz,
+                y: (h - hit.point.y) * tsz,
+            },
+            normal: Vec2 {
+                x: hit.normal.x,
+                y: -hit.normal.y,
+            },
+        })
+    }
+    // Whether nothing solid lies on the straight line between two world positions.
+    // The tiles a and b are standing on don't block the view.
+    pub fn has_line_of_sight(&self, a: WorldPos, b: WorldPos) -> bool {
+        let dir = b - a;
+        match self.raycast(a, dir, dir.mag_sq().sqrt()) {
+            Some(hit) => GridPos::from(hit.cell) == self.world_to_grid(b),
+            None => true,
+        }
+    }
+    // The tiles that can be seen from pos within radius tiles; solid tiles block sight.
+    pub fn field_of_view(&self, pos: WorldPos, radius: usize) -> HashSet<GridPos> {
+        self.visible_coords(pos, radius)
+            .into_iter()
+            .map(GridPos::from)
+            .collect()
+    }
+    fn visible_coords(&self, pos: WorldPos, radius: usize) -> HashSet<grid::Coord> {
+        fov::field_of_view(
+            &self.grid,
+            self.world_to_grid(pos).into(),
+            radius,
+            |_, t| self.tileset[*t as usize].solid,
+        )
+    }
+    // Once fog is on, tiles that haven't been seen yet are drawn black and tiles that were seen
+    // but aren't in view right now are drawn dimmed.  Call update_fog each tick to reveal tiles.
+    pub fn enable_fog(&mut self) {
+        if self.fog.is_none() {
+            self.fog = Some(Fog::new(self.grid.width(), self.grid.height()));
+        }
+    }
+    pub fn disable_fog(&mut self) {
+        self.fog = None;
+    }
+    pub fn fog(&self) -> Option<&Fog> {
+        self.fog.as_ref()
+    }
+    pub fn update_fog(&mut self, pos: WorldPos, radius: usize) {
+        if self.fog.is_none() {
+            return;
+        }
+        let visible = self.visible_coords(pos, radius);
+        if let Some(fog) = &mut self.fog {
+            fog.update(&visible);
+        }
+    }
+    // Whether an entity at pos should be drawn: always without fog, otherwise only in view.
+    pub fn is_visible_at(&self, pos: WorldPos) -> bool {
+        self.fog.as_ref().is_none_or(|fog| {
+            self.cell_at(pos)
+                .is_some_and(|coord| fog.is_visible(coord.into()))
+        })
+    }
+    // Where the first `player` start is, if there is one.
+    pub fn player_start(&self) -> Option<WorldPos> {
+        self.starts
+            .iter()
+            .find(|(t, _)| t.name() == "player")
+            .map(|(_, pos)| *pos)
+    }
+    // Every non-solid tile that can be walked to from pos.
+    pub fn reachable_from(&self, pos: WorldPos) -> Vec<GridPos> {
+        self.grid
+            .flood_fill(self.world_to_grid(pos).into(), |_, t| {
+                !self.tileset[*t as usize].solid
+            })
+            .into_iter()
+            .map(GridPos::from)
+            .collect()
+    }
+    // Starts (enemies, doors, ...) that the player can't walk to from the player start,
+    // including ones placed on solid tiles.  Everything is unreachable if there's no player start.
+    pub fn unreachable_starts(&self) -> Vec<&(EntityType, WorldPos)> {
+        let reachable: HashSet<_> = self
+            .player_start()
+            .map(|p| self.reachable_from(p).into_iter().collect())
+            .unwrap_or_default();
+        self.starts
+            .iter()
+            .filter(|(_, pos)| !reachable.contains(&self.world_to_grid(*pos)))
+            .collect()
+    }
+    // Open tiles on the edge of the map, where the player can walk off of it.
+    pub fn exits(&self) -> Vec<GridPos> {
+        let (w, h) = (self.grid.width(), self.grid.height());
+        (0..w * h)
+            .map(|idx| GridPos::from(self.grid.index_to_coord(idx).unwrap()))
+            .filter(|&GridPos { x, y }| x == 0 || y == 0 || x == w - 1 || y == h - 1)
+            .filter(|&c| !self.get_tile(c).unwrap().solid)
+            .collect()
+    }
+    // Exits the player can get to from the player start, not counting the tile they start on.
+    // A maze with none of these can't be solved.
+    pub fn reachable_exits(&self) -> Vec<GridPos> {
+        let Some(start) = self.player_start() else {
+            return vec![];
+        };
+        let reachable: HashSet<_> = self.reachable_from(start).into_iter().collect();
+        let start = self.world_to_grid(start);
+        self.exits()
+            .into_iter()
+            .filter(|c| *c != start && reachable.contains(c))
+            .collect()
+    }
+    // Groups of open tiles that are walled off from the player start, where an enemy could end up stuck.
+    pub fn pockets(&self) -> Vec<Vec<GridPos>> {
+        let (labels, count) = self
+            .grid
+            .label_regions(|_, t| !self.tileset[*t as usize].solid);
+        let player_region = self
+            .player_start()
+            .and_then(|p| {
+                let GridPos { x, y } = self.world_to_grid(p);
+                labels.get(x, y).copied()
+            })
+            .flatten();
+        let mut pockets = vec![vec![]; count];
+        for idx in 0..self.grid.width() * self.grid.height() {
+            if let Some(region) = labels[idx] {
+                pockets[region].push(self.grid.index_to_coord(idx).unwrap().into());
+            }
+        }
+        pockets
+            .into_iter()
+            .enumerate()
+            .filter(|(region, _)| Some(*region) != player_region)
+            .map(|(_, cells)| cells)
+            .collect()
+    }
+    // Turns a grid path into world-space points at the center of each tile.
+    pub fn path_to_world(&self, path: &[GridPos]) -> Vec<WorldPos> {
+        path.iter().map(|c| self.tile_center(*c)).collect()
+    }
     // pub fn tile_rect_for_index(&self, idx: usize) -> Option<Rect> {
     //     self.grid
     //         .index_to_coord(idx)
     //         .map(|c| self.tile_rect_for_coord(c))
     // }
-    pub fn tile_rect_for_coord(&self, coord: grid::Coord) -> Rect {
+    pub fn tile_rect_for_coord(&self, coord: GridPos) -> Rect {
         let world = self.grid_to_world(coord);
         Rect {
             x: world.x,
@@ -359,15 +1598,679 @@ impl Level {
     pub fn height(&self) -> usize {
         self.grid.height()
     }
+    // How big a tile is in world units, from the level's metadata line.  Entities are a tile big,
+    // so games should size and place things with this rather than assuming 16.
+    pub fn tile_size(&self) -> usize {
+        self.tile_size as usize
+    }
 }
 
 #[derive(Debug)]
 pub struct Tileset {
     tiles: Vec<TileData>,
+    symbols: Vec<String>,
+    autotiles: Vec<Option<Autotile>>,
+    // each tile's weighted looks, starting with its own region
+    variants: Vec<Vec<(u32, SheetRegion)>>,
+}
+impl Tileset {
+    // Picks one of a tile's variants for a cell; the same seed and cell always get the same one.
+    fn variant(&self, tile: usize, seed: u64, (x, y): grid::Coord) -> SheetRegion {
+        let choices = &self.variants[tile];
+        let total: u64 = choices.iter().map(|(w, _)| *w as u64).sum();
+        if choices.len() < 2 || total == 0 {
+            return self.tiles[tile].sheet_region;
+        }
+        // splitmix64 of the seed and coordinates
+        let mut z = seed ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9E3779B97F4A7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        let mut pick = (z ^ (z >> 31)) % total;
+        for (weight, region) in choices {
+            if pick < *weight as u64 {
+                return *region;
+            }
+            pick -= *weight as u64;
+        }
+        unreachable!()
+    }
+    fn symbol_index(&self, sym: &str) -> Option<TileId> {
+        self.symbols
+            .iter()
+            .position(|s| s == sym)
+            .map(|i| i as TileId)
+    }
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AutotileKind {
+    // looks at the four edge neighbors: N E S W
+    Edges,
+    // looks at all eight neighbors: N NE E SE S SW W NW
+    Blob,
+}
+
+#[derive(Clone, Debug)]
+struct Autotile {
+    kind: AutotileKind,
+    // neighbor bits that matter, the values they must have, and the region to use if they do
+    rules: Vec<(u8, u8, SheetRegion)>,
+}
+
+impl Autotile {
+    fn add_rule(&mut self, pattern: &str, region: SheetRegion, line: &str) {
+        let len = match self.kind {
+            AutotileKind::Edges => 4,
+            AutotileKind::Blob => 8,
+        };
+        assert_eq!(
+            pattern.len(),
+            len,
+            "Autotile pattern {pattern} should have {len} characters in {line}"
+        );
+        let (mut care, mut want) = (0, 0);
+        for (bit, c) in pattern.chars().enumerate() {
+            match c {
+                '1' => {
+                    care |= 1 << bit;
+                    want |= 1 << bit;
+                }
+                '0' => care |= 1 << bit,
+                'x' => {}
+                _ => panic!("Autotile patterns are made of 0, 1 and x, not {c} in {line}"),
+            }
+        }
+        self.rules.push((care, want, region));
+    }
+    // `same(dx, dy)` says whether the neighbor at that offset (y down) has the same symbol.
+    fn region(&self, same: impl Fn(isize, isize) -> bool) -> Option<SheetRegion> {
+        let (n, e, s, w) = (same(0, -1), same(1, 0), same(0, 1), same(-1, 0));
+        let bits = match self.kind {
+            AutotileKind::Edges => [n, e, s, w].to_vec(),
+            AutotileKind::Blob => [
+                n,
+                n && e && same(1, -1),
+                e,
+                e && s && same(1, 1),
+                s,
+                s && w && same(-1, 1),
+                w,
+                w && n && same(-1, -1),
+            ]
+            .to_vec(),
+        };
+        let mask = bits
+            .into_iter()
+            .enumerate()
+            .fold(0u8, |mask, (bit, set)| mask | ((set as u8) << bit));
+        self.rules
+            .iter()
+            .find(|(care, want, _)| mask & care == *want)
+            .map(|(_, _, region)| *region)
+    }
+}
+
+// The next free tile id for a legend entry.
+fn next_tile_id(legend: &HashMap<String, (TileId, TileData)>, sym: &str) -> TileId {
+    TileId::try_from(legend.len()).unwrap_or_else(|_| {
+        panic!(
+            "Too many legend entries: {sym} would be number {}, but levels can only have {}",
+            legend.len() + 1,
+            TileId::MAX as usize + 1
+        )
+    })
+}
+
+// Where an entity starting on grid cell x, y goes in the world: the middle of the tile.
+fn start_pos(x: usize, y: usize, tsz: u16, h: u16) -> WorldPos {
+    let tsz = tsz as usize;
+    WorldPos {
+        x: (x * tsz) as f32 + tsz as f32 / 2.0,
+        y: ((h as usize - y) * tsz) as f32 - tsz as f32 / 2.0,
+    }
+}
+
+const BINARY_MAGIC: &[u8] = b"LVLB";
+// 2 added metadata properties, 3 added bounds, 4 added tile friction
+const BINARY_VERSION: u16 = 4;
+
+struct Writer(Vec<u8>);
+impl Writer {
+    fn u16(&mut self, num: u16) {
+        self.0.extend_from_slice(&num.to_le_bytes());
+    }
+    fn len(&mut self, len: usize) {
+        let len = u32::try_from(len).expect("Too many things in level to compile");
+        self.0.extend_from_slice(&len.to_le_bytes());
+    }
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+    fn region(&mut self, region: SheetRegion) {
+        self.u16(region.x);
+        self.u16(region.y);
+        self.0.extend_from_slice(&region.w.to_le_bytes());
+        self.0.extend_from_slice(&region.h.to_le_bytes());
+        self.u16(region.depth);
+    }
 }
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    sheet_layer: u16,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> &'a [u8] {
+        assert!(count <= self.bytes.len(), "Compiled level ends too soon");
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        taken
+    }
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.take(2).try_into().unwrap())
+    }
+    fn len(&mut self) -> usize {
+        u32::from_le_bytes(self.take(4).try_into().unwrap()) as usize
+    }
+    fn str(&mut self) -> String {
+        let len = self.len();
+        String::from_utf8(self.take(len).to_vec())
+            .expect("Compiled level has a string that isn't UTF-8")
+    }
+    fn region(&mut self) -> SheetRegion {
+        let (x, y) = (self.u16(), self.u16());
+        let w = i16::from_le_bytes(self.take(2).try_into().unwrap());
+        let h = i16::from_le_bytes(self.take(2).try_into().unwrap());
+        SheetRegion::new(self.sheet_layer, x, y, self.u16(), w, h)
+    }
+}
+
+fn parse_region<'a>(
+    chunks: &mut impl Iterator<Item = &'a str>,
+    sheet_layer: u16,
+    line: &str,
+) -> SheetRegion {
+    let mut next = |what: &str| {
+        chunks
+            .next()
+            .unwrap_or_else(|| panic!("No sheet {what} in legend line {line}"))
+    };
+    let (x, y, w, h) = (next("x"), next("y"), next("w"), next("h"));
+    fn parse<T: FromStr>(s: &str, what: &str, line: &str) -> T {
+        T::from_str(s).unwrap_or_else(|_| panic!("Couldn't parse sheet {what} {s} in {line}"))
+    }
+    SheetRegion::new(
+        sheet_layer,
+        parse(x, "x", line),
+        parse(y, "y", line),
+        16,
+        parse(w, "w", line),
+        parse(h, "h", line),
+    )
+}
+
 impl std::ops::Index<usize> for Tileset {
     type Output = TileData;
     fn index(&self, index: usize) -> &Self::Output {
         &self.tiles[index]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn cell(x: usize, y: usize) -> GridPos {
+        GridPos { x, y }
+    }
+    #[test]
+    fn test_image_matches_text() {
+        let text = "room 3 2 16
+====
+. o 0 0 16 16
+# s 16 0 16 16
+====
+# . #
+# . .
+====
+player 1 1
+enemy 2 1
+";
+        let mapping = "room 16
+====
+. o 0 0 16 16
+# s 16 0 16 16
+====
+000000 #
+ffffff .
+====
+00ff00 player .
+ff0000 enemy .
+";
+        let mut img = image::RgbaImage::new(3, 2);
+        let colors = [
+            [0, 0, 0],
+            [255, 255, 255],
+            [0, 0, 0],
+            [0, 0, 0],
+            [0, 255, 0],
+            [255, 0, 0],
+        ];
+        for (px, [r, g, b]) in img.pixels_mut().zip(colors) {
+            *px = image::Rgba([r, g, b, 255]);
+        }
+        let from_text = Level::from_str(text, 0, 0);
+        let from_img = Level::from_image(&img, mapping, 0, 0);
+        assert_eq!(from_text.name(), from_img.name());
+        assert_eq!((from_text.width(), from_text.height()), (3, 2));
+        assert_eq!((from_img.width(), from_img.height()), (3, 2));
+        assert!(from_text.grid.row_iter().eq(from_img.grid.row_iter()));
+        assert_eq!(from_text.starts(), from_img.starts());
+    }
+    #[test]
+    fn test_autotile() {
+        let text = "walls 4 3 16
+====
+. o 0 0 16 16
+w s auto4 16 0 16 16
+w 1x1x 32 0 16 16
+w 0x0x 48 0 16 16
+====
+. w . .
+. w . .
+. . . .
+====
+";
+        let mut level = Level::from_str(text, 0, 0);
+        let x_of = |level: &Level, c| level.tile_region(c).unwrap().x;
+        // the top of the map counts as wall, so the top cell is a vertical run
+        assert_eq!(x_of(&level, cell(1, 0)), 32);
+        assert_eq!(x_of(&level, cell(1, 1)), 16);
+        assert_eq!(x_of(&level, cell(0, 0)), 0);
+        level.set_tile(cell(1, 1), ".");
+        assert_eq!(x_of(&level, cell(1, 0)), 16);
+        level.set_tile(cell(1, 0), ".");
+        level.set_tile(cell(2, 1), "w");
+        assert_eq!(x_of(&level, cell(2, 1)), 48);
+        level.reset_tiles();
+        assert_eq!(x_of(&level, cell(1, 1)), 16);
+        assert_eq!(x_of(&level, cell(2, 1)), 0);
+    }
+    #[test]
+    fn test_variants() {
+        let text = "floor 8 8 16
+seed 7
+====
+. o 0 0 16 16 2
+. variant 1 16 0 16 16
+. variant 1 32 0 16 16
+====
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+. . . . . . . .
+====
+";
+        let level = Level::from_str(text, 0, 0);
+        let again = Level::from_str(text, 0, 0);
+        let mut counts = [0; 3];
+        for idx in 0..64 {
+            let c = GridPos::from(level.grid.index_to_coord(idx).unwrap());
+            let x = level.tile_region(c).unwrap().x;
+            assert_eq!(x, again.tile_region(c).unwrap().x);
+            counts[x as usize / 16] += 1;
+            assert!(!level.get_tile(c).unwrap().solid);
+        }
+        assert!(counts.iter().all(|&n| n > 0));
+        let reseeded = Level::from_str(&text.replace("seed 7", "seed 8"), 0, 0);
+        assert!((0..64).any(|idx| {
+            let c = GridPos::from(level.grid.index_to_coord(idx).unwrap());
+            level.tile_region(c).unwrap().x != reseeded.tile_region(c).unwrap().x
+        }));
+        // generated levels take their seed from the header too, so the same header and map look the same
+        let header = "floor 16\nseed 7\n====\n. o 0 0 16 16 2\n. variant 1 16 0 16 16\n. variant 1 32 0 16 16\n";
+        let generated = Level::from_symbols(header, &Grid::new(8, 8, vec!["."; 64]), &[], 0, 0);
+        assert_eq!(generated.seed(), 7);
+        assert!((0..64).all(|idx| {
+            let c = GridPos::from(level.grid.index_to_coord(idx).unwrap());
+            level.tile_region(c).unwrap().x == generated.tile_region(c).unwrap().x
+        }));
+    }
+    fn legend_of(count: usize) -> String {
+        let mut text = String::from("big 3 1 16\n====\n");
+        for i in 0..count {
+            text.push_str(&format!("t{i} o 0 0 16 16\n"));
+        }
+        text
+    }
+    #[test]
+    fn test_many_symbols() {
+        let mut text = legend_of(300);
+        text.push_str("木/wall s 16 0 16 16\n====\nt0 t299 木/wall\n====\n");
+        let level = Level::from_str(&text, 0, 0);
+        assert_eq!(level.tile_symbol(cell(0, 0)), Some("t0"));
+        assert_eq!(level.tile_symbol(cell(1, 0)), Some("t299"));
+        assert_eq!(level.tile_symbol(cell(2, 0)), Some("木/wall"));
+        assert!(level.get_tile(cell(2, 0)).unwrap().solid);
+    }
+    #[test]
+    #[should_panic(expected = "Too many legend entries")]
+    fn test_too_many_symbols() {
+        let mut text = legend_of(TileId::MAX as usize + 2);
+        text.push_str("====\nt0 t0 t0\n====\n");
+        Level::from_str(&text, 0, 0);
+    }
+    #[test]
+    fn test_set_and_reset_tiles() {
+        let mut level = Level::from_str(
+            "room 3 1 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n# . #\n",
+            0,
+            0,
+        );
+        let middle = level.grid_to_world(cell(1, 0)) + Vec2 { x: 8.0, y: 8.0 };
+        assert!(!level.get_tile_at(middle).solid);
+        assert!(level.set_tile(cell(1, 0), "#"));
+        assert!(level.get_tile_at(middle).solid);
+        assert_eq!(level.tile_symbol(cell(1, 0)), Some("#"));
+        assert!(level.is_modified(cell(1, 0)));
+        assert!(!level.set_tile(cell(3, 0), "#"));
+        level.reset_tiles();
+        assert_eq!(level.tile_symbol(cell(1, 0)), Some("."));
+        assert!(!level.is_modified(cell(1, 0)));
+    }
+    #[test]
+    fn test_prefabs() {
+        let load = |name: &str| match name {
+            "shrine" => String::from(
+                "shrine 2 1 16\n====\n. o 64 0 16 16\n* s 32 0 16 16\n====\n* .\n====\nenemy 1 0\n",
+            ),
+            "pair" => String::from(
+                "pair 2 1 16\n====\n# o 48 0 16 16\n====\n# #\n====\nstamp 0 0 shrine 180\n",
+            ),
+            _ => panic!("no prefab {name}"),
+        };
+        let level = Level::from_str_with_prefabs(
+            "room 4 3 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n# # # #\n# # # #\n# # # #\n====\nplayer 0 0\nstamp 1 1 shrine cw\nstamp 2 0 pair\n",
+            0,
+            0,
+            &load,
+        );
+        // the host's . wins over the prefab's, the prefab's * is added
+        assert_eq!(level.tile_symbol(cell(1, 1)), Some("*"));
+        assert_eq!(level.tile_symbol(cell(1, 2)), Some("."));
+        assert!(level.get_tile_at(level.grid_to_world(cell(1, 1))).solid);
+        assert_eq!(level.tile_region(cell(1, 2)).unwrap().x, 0);
+        // the nested stamp was turned around inside pair
+        assert_eq!(level.tile_symbol(cell(2, 0)), Some("."));
+        assert_eq!(level.tile_symbol(cell(3, 0)), Some("*"));
+        let enemies: Vec<_> = level
+            .starts()
+            .iter()
+            .filter(|(t, _)| t.name() == "enemy")
+            .map(|(_, pos)| level.world_to_grid(*pos))
+            .collect();
+        assert_eq!(enemies, vec![cell(1, 2), cell(2, 0)]);
+    }
+    #[test]
+    #[should_panic(expected = "Prefab a ends up stamping itself: a -> b -> a")]
+    fn test_prefab_cycle() {
+        let load = |name: &str| match name {
+            "a" => String::from("a 1 1 16\n====\n. o 0 0 16 16\n====\n.\n====\nstamp 0 0 b\n"),
+            "b" => String::from("b 1 1 16\n====\n. o 0 0 16 16\n====\n.\n====\nstamp 0 0 a\n"),
+            _ => panic!("no prefab {name}"),
+        };
+        Level::from_str_with_prefabs(
+            "room 1 1 16\n====\n. o 0 0 16 16\n====\n.\n====\nstamp 0 0 a\n",
+            0,
+            0,
+            &load,
+        );
+    }
+    #[test]
+    fn test_start_props() {
+        let level = Level::from_str(
+            "room 3 1 16\n====\n. o 0 0 16 16\n====\n. . .\n====\nenemy 1 0 kind=bat hp=5 patrol=left 7\n",
+            0,
+            0,
+        );
+        let (enemy, _) = &level.starts()[0];
+        assert_eq!(enemy.prop::<String>("kind").unwrap(), "bat");
+        assert_eq!(enemy.prop::<u32>("hp"), Ok(5));
+        assert_eq!(enemy.prop_or("speed", 2.5), Ok(2.5));
+        assert!(enemy.has_prop("patrol"));
+        assert_eq!(enemy.numbers(), &[7]);
+        assert!(matches!(
+            enemy.prop::<u32>("patrol"),
+            Err(PropError::Invalid { .. })
+        ));
+        assert!(matches!(
+            enemy.prop_or::<u32>("patrol", 1),
+            Err(PropError::Invalid { .. })
+        ));
+        assert_eq!(
+            enemy.prop::<u32>("speed").unwrap_err().to_string(),
+            "enemy start has no speed"
+        );
+    }
+    #[test]
+    fn test_binary_round_trip() {
+        let text = "room 3 2 16\nseed 7\n====\n. o 0 0 16 16\n. variant 2 64 0 16 16\nw s auto4 16 0 16 16\nw 0x1x 32 0 16 16\n====\nw . .\nw w .\n====\nplayer 1 0\nenemy 2 1 hp=5 guard 3\n";
+        let level = Level::from_str(text, 0, 0);
+        let bytes = level.to_bytes();
+        let loaded = Level::from_bytes(&bytes, 0, 0);
+        assert_eq!(loaded.name(), "room");
+        assert_eq!(loaded.seed(), 7);
+        assert_eq!(loaded.starts(), level.starts());
+        for c in level.grid.bounds().coords().map(GridPos::from) {
+            assert_eq!(loaded.tile_symbol(c), level.tile_symbol(c));
+            assert_eq!(
+                loaded.tile_region(c).unwrap().x,
+                level.tile_region(c).unwrap().x
+            );
+        }
+        assert_eq!(loaded.to_bytes(), bytes);
+        // set_tile still knows the symbols after a round trip
+        let mut loaded = loaded;
+        assert!(loaded.set_tile(cell(2, 0), "w"));
+    }
+    #[test]
+    #[should_panic(expected = "version")]
+    fn test_binary_version_mismatch() {
+        let mut bytes =
+            Level::from_str("room 1 1 16\n====\n. o 0 0 16 16\n====\n.\n", 0, 0).to_bytes();
+        bytes[4] = 99;
+        Level::from_bytes(&bytes, 0, 0);
+    }
+    #[test]
+    fn test_metadata() {
+        let header = "cellar 16\ntitle = The Cellar\ntime_limit=90\nseed 3\n====\n. o 0 0 16 16\n";
+        let symbols = Grid::new(2, 1, vec!["."; 2]);
+        let level = Level::from_symbols(header, &symbols, &[], 0, 0);
+        assert_eq!(level.seed(), 3);
+        assert_eq!(level.meta::<String>("title").unwrap(), "The Cellar");
+        assert_eq!(level.meta::<u32>("time_limit"), Ok(90));
+        assert_eq!(level.meta_or("spawn_rate", 0.5), Ok(0.5));
+        assert_eq!(
+            level.meta::<f32>("title").unwrap_err().to_string(),
+            "level cellar has title=The Cellar, which isn't a valid f32"
+        );
+        let loaded = Level::from_bytes(&level.to_bytes(), 0, 0);
+        assert_eq!(loaded.metadata(), level.metadata());
+    }
+    #[test]
+    fn test_coordinate_spaces() {
+        let level = Level::from_str(
+            "room 3 2 16\n====\n. o 0 0 16 16\n====\n. . .\n. . .\n====\nplayer 2 0\n",
+            0,
+            0,
+        );
+        // row 0 is the top row, which is the higher one in the world
+        assert_eq!(
+            level.grid_to_world(cell(2, 0)),
+            WorldPos { x: 32.0, y: 16.0 }
+        );
+        assert_eq!(level.tile_center(cell(2, 0)), WorldPos { x: 40.0, y: 24.0 });
+        assert_eq!(level.player_start(), Some(level.tile_center(cell(2, 0))));
+        // every point of a cell maps back to it, right up to its top and right edges
+        let top_right = Vec2 { x: 15.5, y: 15.5 };
+        for c in level.grid.bounds().coords().map(GridPos::from) {
+            assert_eq!(level.world_to_grid(level.tile_center(c)), c);
+            assert_eq!(level.world_to_grid(level.grid_to_world(c)), c);
+            assert_eq!(level.world_to_grid(level.grid_to_world(c) + top_right), c);
+        }
+        let camera = Camera2D {
+            screen_pos: [10.0, 4.0],
+            screen_size: [32.0, 32.0],
+        };
+        let on_screen = camera.to_screen(level.tile_center(cell(0, 1)));
+        assert_eq!(on_screen, ScreenPos { x: -2.0, y: 4.0 });
+        assert_eq!(camera.to_world(on_screen), level.tile_center(cell(0, 1)));
+    }
+    #[test]
+    fn test_out_of_bounds() {
+        let text =
+            "room 4 3 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n. # . .\n. # . .\n. # . .\n";
+        let left_of_map = WorldPos { x: -4.0, y: 24.0 };
+        let solid = Level::from_str(text, 0, 0);
+        assert_eq!(solid.bounds(), OutOfBounds::Solid);
+        assert!(solid.get_tile_at(left_of_map).solid);
+        assert_eq!(solid.cell_at(left_of_map), None);
+        let edge = Rect {
+            x: -8.0,
+            y: 16.0,
+            w: 16,
+            h: 16,
+        };
+        assert!(solid
+            .tiles_within(edge)
+            .any(|(c, r, t)| c.is_none() && t.solid && r.overlap(edge).is_some()));
+        let void = Level::from_str(&text.replacen("====", "bounds void\n====", 1), 0, 0);
+        assert!(!void.get_tile_at(left_of_map).solid);
+        assert!(void.tiles_within(edge).all(|(c, _, _)| c.is_some()));
+        let wrap = Level::from_str(&text.replacen("====", "bounds wrap\n====", 1), 0, 0);
+        assert_eq!(wrap.cell_at(left_of_map), Some(cell(3, 1)));
+        // the top pixel of the top row is still in the top row, not wrapped around to the bottom
+        let top = WorldPos { x: 8.0, y: 47.5 };
+        assert_eq!(wrap.world_to_grid(top), cell(0, 0));
+        assert_eq!(solid.cell_at(top), Some(cell(0, 0)));
+        assert!(!solid.get_tile_at(top).solid);
+        assert_eq!(solid.cell_at(WorldPos { x: 8.0, y: 48.0 }), None);
+        assert_eq!(wrap.wrap_pos(left_of_map), WorldPos { x: 60.0, y: 24.0 });
+        // the shortest way from one side to the other is across the edge
+        let offset = wrap.offset(wrap.tile_center(cell(0, 1)), wrap.tile_center(cell(3, 1)));
+        assert_eq!(offset, Vec2 { x: -16.0, y: 0.0 });
+        assert!(wrap
+            .tiles_within(edge)
+            .any(|(c, r, _)| c == Some(cell(3, 1)) && r.x == -16.0));
+        // the wall down column 1 only has a way around through the edges
+        assert_eq!(
+            solid.find_path(cell(0, 1), cell(2, 1), Connectivity::Four),
+            None
+        );
+        let path = wrap
+            .find_path(cell(0, 1), cell(2, 1), Connectivity::Four)
+            .unwrap();
+        assert_eq!(path, vec![cell(0, 1), cell(3, 1), cell(2, 1)]);
+        assert_eq!(
+            Level::from_bytes(&wrap.to_bytes(), 0, 0).bounds(),
+            OutOfBounds::Wrap
+        );
+    }
+    #[test]
+    fn test_visible_range() {
+        let mut text = String::from("big 100 50 16\n====\n. o 0 0 16 16\n====\n");
+        for _ in 0..50 {
+            text.push_str(&". ".repeat(100));
+            text.push('\n');
+        }
+        let level = Level::from_str(&text, 0, 0);
+        let camera = Camera2D {
+            screen_pos: [160.0, 0.0],
+            screen_size: [220.0, 140.0],
+        };
+        let (cols, rows) = level.visible_range(&camera, 0);
+        assert_eq!(cols, 10..24);
+        // the bottom of the world is the last grid row
+        assert_eq!(rows, 41..50);
+        let (cols, rows) = level.visible_range(&camera, RENDER_MARGIN);
+        assert!(cols.len() * rows.len() < level.sprite_count_for_view(&camera));
+        let mut trfs = vec![Transform::ZERO; cols.len() * rows.len() + 1];
+        let mut uvs = vec![SheetRegion::ZERO; trfs.len()];
+        assert_eq!(
+            level.render_region_into(&mut trfs, &mut uvs, cols, rows),
+            trfs.len()
+        );
+        assert_eq!(trfs[0].x, 9.0 * 16.0 + 8.0);
+    }
+    #[test]
+    fn test_raycast_world_space() {
+        let level = Level::from_str(
+            "room 3 2 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n. . .\n# . #\n",
+            0,
+            0,
+        );
+        // straight down from the top left tile onto the wall under it
+        let hit = level
+            .raycast(
+                WorldPos { x: 8.0, y: 24.0 },
+                Vec2 { x: 0.0, y: -1.0 },
+                100.0,
+            )
+            .unwrap();
+        assert_eq!(hit.cell, (0, 1));
+        assert_eq!(hit.point, Vec2 { x: 8.0, y: 16.0 });
+        assert_eq!(hit.normal, Vec2 { x: 0.0, y: 1.0 });
+        assert_eq!(hit.distance, 8.0);
+        assert!(
+            level.has_line_of_sight(WorldPos { x: 8.0, y: 24.0 }, WorldPos { x: 40.0, y: 24.0 })
+        );
+        assert!(
+            !level.has_line_of_sight(WorldPos { x: 8.0, y: 24.0 }, WorldPos { x: 40.0, y: -8.0 })
+        );
+    }
+    #[test]
+    fn test_shipped_levels_are_connected() {
+        let maze = Level::from_str(include_str!("../../maze-game/content/maze0.txt"), 0, 0);
+        assert!(!maze.reachable_exits().is_empty(), "maze0 can't be solved");
+        let prefab = |name: &str| match name {
+            "rocks" => include_str!("../../adventure-game/content/rocks.txt").to_string(),
+            "boulder" => include_str!("../../adventure-game/content/boulder.txt").to_string(),
+            "ridge" => include_str!("../../adventure-game/content/ridge.txt").to_string(),
+            _ => panic!("Shipped levels stamp unknown prefab {name}"),
+        };
+        for text in [
+            include_str!("../../adventure-game/content/level1.txt"),
+            include_str!("../../adventure-game/content/level2.txt"),
+            include_str!("../../adventure-game/content/level3.txt"),
+        ] {
+            let level = Level::from_str_with_prefabs(text, 0, 0, &prefab);
+            assert!(level.player_start().is_some());
+            let bytes = level.to_bytes();
+            assert_eq!(Level::from_bytes(&bytes, 0, 0).to_bytes(), bytes);
+            assert_eq!(
+                level.unreachable_starts(),
+                Vec::<&(EntityType, WorldPos)>::new()
+            );
+        }
+    }
+    #[test]
+    fn test_pockets() {
+        let level = Level::from_str(
+            "room 5 3 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n. . # . #\n. . # # #\n# # # . .\n====\nplayer 0 0\nenemy 3 0\n",
+            0,
+            0,
+        );
+        let mut pockets = level.pockets();
+        pockets.sort();
+        assert_eq!(
+            pockets,
+            vec![vec![cell(3, 0)], vec![cell(3, 2), cell(4, 2)]]
+        );
+        assert_eq!(level.unreachable_starts().len(), 1);
+        assert_eq!(level.unreachable_starts()[0].0.name(), "enemy");
+        assert!(level.reachable_exits().contains(&cell(1, 0)));
+        assert!(!level.reachable_exits().contains(&cell(4, 2)));
+    }
+}
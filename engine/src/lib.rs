@@ -1,18 +1,29 @@
 pub mod geom;
 pub mod level;
 pub mod grid;
+pub mod pathfinding;
+pub mod raycast;
+pub mod fov;
+pub mod maze;
+pub mod dungeon;
+pub mod cave;
+pub mod collision;
+pub mod physics;
 use std::vec;
 
 use rand::Rng;
-use geom::Vec2;
+use geom::{Vec2, WorldPos};
 use geom::Rect;
+use collision::{Body, Priority};
 use level::Level;
+use physics::Kinematic;
 use assets_manager::AssetCache;
 use frenderer::{
     input::Input, sprites::Camera2D, Immediate
 };
 const DT: f32 = 1.0 / 60.0;
-const TILE_SZ: usize = 16;
+// how hard spread_out tries to untangle bodies each time it's called
+const SEPARATION_ITERATIONS: usize = 4;
 
 #[derive(Clone, Debug)]
 pub struct Contact {
@@ -45,8 +56,10 @@ impl Dir {
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Pos {
-    pub pos: Vec2,
+    pub pos: WorldPos,
     pub dir: Dir,
+    // velocity and how it speeds up and slows down, for games that move things with physics::Kinematic::step
+    pub motion: Kinematic,
 }
 pub struct World {
     pub camera: Camera2D,
@@ -54,6 +67,8 @@ pub struct World {
     pub levels: Vec<Level>,
     pub enemies: Vec<(Pos, usize)>,
     pub player: Pos,
+    // how enemies placed by enter_level and spawn_enemies move
+    pub enemy_motion: Kinematic,
     pub paused: bool,
     pub game_end: bool,
 }
@@ -62,32 +77,55 @@ impl World {
     pub fn level(&self) -> &Level {
         &self.levels[self.current_level]
     }
-    pub fn enter_level(&mut self, player_pos: Vec2) {
+    pub fn enter_level(&mut self, player_pos: WorldPos) {
         self.enemies.clear();
         self.player.pos = player_pos;
+        self.player.motion.stop();
+        // the number that goes with each enemy is its hit points, 1 unless its start has an hp property
         for (etype, pos) in self.levels[self.current_level].starts().iter() {
             if etype.name() == "enemy" { self.enemies.push((Pos {
                 pos: *pos,
                 dir: Dir::S,
-            }, 1)) };
+                motion: self.enemy_motion,
+            }, etype.prop_or("hp", 1).unwrap_or_else(|e| panic!("Bad enemy start: {e}")))) };
         }
     }
     pub fn spawn_enemies(&mut self) {
         let mut rng = rand::thread_rng();
-        let mut randx = rng.gen_range(2*TILE_SZ..self.levels[self.current_level].width()*TILE_SZ-2*TILE_SZ);
-        let mut randy = rng.gen_range(2*TILE_SZ..self.levels[self.current_level].height()*TILE_SZ-2*TILE_SZ);
-        while ((randx as f32 - self.player.pos.x).abs() < 48.0) && ((randy as f32 - self.player.pos.y).abs() < 48.0)
-        && !self.level().get_tile_at(Vec2{x:randx as f32, y:randy as f32}).unwrap().solid  {
-            randx = rng.gen_range(2..self.levels[self.current_level].width()*TILE_SZ);
-            randy = rng.gen_range(2..self.levels[self.current_level].height()*TILE_SZ);
+        let tsz = self.level().tile_size();
+        let mut randx = rng.gen_range(2*tsz..self.levels[self.current_level].width()*tsz-2*tsz);
+        let mut randy = rng.gen_range(2*tsz..self.levels[self.current_level].height()*tsz-2*tsz);
+        // try again if that's within three tiles of the player or inside a wall
+        let near = 3.0 * tsz as f32;
+        while ((randx as f32 - self.player.pos.x).abs() < near) && ((randy as f32 - self.player.pos.y).abs() < near)
+        || self.level().get_tile_at(WorldPos{x:randx as f32, y:randy as f32}).solid  {
+            randx = rng.gen_range(2..self.levels[self.current_level].width()*tsz);
+            randy = rng.gen_range(2..self.levels[self.current_level].height()*tsz);
         } 
         let monster = Pos {
-            pos: Vec2{x: randx as f32, y: randy as f32},
+            pos: WorldPos{x: randx as f32, y: randy as f32},
             dir: Dir::S,
+            motion: self.enemy_motion,
         };
         self.enemies.push((monster, 1));
         
     }
+    // Spreads the player, the enemies and any extra bodies out so they don't stack up on each other (see
+    // collision::separate), with the player and enemies a tile big and pushing with the given priorities.
+    // Moves the player and enemies, and returns how far each extra body moved for the game to move its things.
+    pub fn spread_out(&mut self, player: Priority, enemies: Priority, extra: &[Body]) -> Vec<Vec2> {
+        let tsz = self.level().tile_size() as u16;
+        let mut bodies = vec![Body::centered(self.player.pos, tsz, tsz, player)];
+        bodies.extend(self.enemies.iter().map(|e| Body::centered(e.0.pos, tsz, tsz, enemies)));
+        bodies.extend_from_slice(extra);
+        let mut moved =
+            collision::separate(&mut bodies, Some(self.level()), SEPARATION_ITERATIONS).into_iter();
+        self.player.pos += moved.next().unwrap();
+        for (enemy, by) in self.enemies.iter_mut().zip(&mut moved) {
+            enemy.0.pos += by;
+        }
+        moved.collect()
+    }
     pub fn set_camera(&mut self, camera: Camera2D) {
         self.camera = camera;
     }
@@ -103,6 +141,9 @@ impl World {
     pub fn set_player(&mut self, player: Pos) {
         self.player = player;
     }
+    pub fn set_enemy_motion(&mut self, motion: Kinematic) {
+        self.enemy_motion = motion;
+    }
     pub fn pause(&mut self) {
         self.paused = !self.paused;
     }
@@ -142,9 +183,11 @@ pub fn main_loop<G:Game> (cache: AssetCache, size_x: f32, size_y: f32) where G:
                 levels: vec![],
                 enemies: vec![],
                 player: Pos {
-                    pos: Vec2 {x: 0.0, y: 0.0},
+                    pos: WorldPos {x: 0.0, y: 0.0},
                     dir: Dir::S,
+                    motion: Kinematic::default(),
                 },
+                enemy_motion: Kinematic::default(),
                 paused: false,
                 game_end: false,
             };
@@ -0,0 +1,286 @@
+use crate::grid::{Coord, Grid};
+use crate::level::Level;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MazeAlgorithm {
+    // long twisty corridors with few branches
+    RecursiveBacktracker,
+    // lots of short dead ends branching off everywhere
+    Prim,
+    // a uniformly random maze, with no bias either way
+    Wilson,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MazeSettings {
+    // size in maze cells; the tile grid is 2 * width + 1 by 2 * height + 1 since walls take up tiles too
+    pub width: usize,
+    pub height: usize,
+    pub algorithm: MazeAlgorithm,
+    // chance (0 to 1) of knocking out each dead end to make loops; 0 is a perfect maze
+    pub braid: f32,
+    // the same seed and settings always give the same maze
+    pub seed: u64,
+}
+
+// The tile grid of a maze: true is wall.  Cell (x, y) of the maze is tile (2x + 1, 2y + 1)
+// and the tiles between neighboring cells are knocked out when there's a passage between them.
+// The outer border is left solid.
+pub fn generate(settings: &MazeSettings) -> Grid<bool> {
+    assert!(
+        settings.width > 0 && settings.height > 0,
+        "Mazes need at least one cell"
+    );
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let mut maze = Carver {
+        width: settings.width,
+        height: settings.height,
+        walls: Grid::new(
+            settings.width * 2 + 1,
+            settings.height * 2 + 1,
+            vec![true; (settings.width * 2 + 1) * (settings.height * 2 + 1)],
+        ),
+    };
+    match settings.algorithm {
+        MazeAlgorithm::RecursiveBacktracker => maze.backtrack(&mut rng),
+        MazeAlgorithm::Prim => maze.prim(&mut rng),
+        MazeAlgorithm::Wilson => maze.wilson(&mut rng),
+    }
+    maze.braid(settings.braid, &mut rng);
+    maze.walls
+}
+
+// A maze level with the player standing in an opening in the left wall next to the top left cell
+// and an exit opening in the right wall next to the bottom right cell.
+// The header is the metadata and legend of the level (see Level::from_symbols).
+pub fn maze_level(
+    settings: &MazeSettings,
+    header: &str,
+    wall: &str,
+    floor: &str,
+    sheet: usize,
+    sheet_layer: u16,
+) -> Level {
+    let mut walls = generate(settings);
+    let (w, h) = (walls.width(), walls.height());
+    let entrance = (0, 1);
+    walls[entrance] = false;
+    walls[(w - 1, h - 2)] = false;
+    let symbols = walls.map(|_, &solid| if solid { wall } else { floor });
+    Level::from_symbols(
+        header,
+        &symbols,
+        &[("player", entrance)],
+        sheet,
+        sheet_layer,
+    )
+}
+
+struct Carver {
+    width: usize,
+    height: usize,
+    walls: Grid<bool>,
+}
+
+impl Carver {
+    fn tile((x, y): Coord) -> Coord {
+        (x * 2 + 1, y * 2 + 1)
+    }
+    fn cells(&self) -> impl Iterator<Item = Coord> {
+        let w = self.width;
+        (0..w * self.height).map(move |idx| (idx % w, idx / w))
+    }
+    fn neighbors(&self, (x, y): Coord) -> Vec<Coord> {
+        let mut ns = vec![];
+        if x > 0 {
+            ns.push((x - 1, y));
+        }
+        if y > 0 {
+            ns.push((x, y - 1));
+        }
+        if x + 1 < self.width {
+            ns.push((x + 1, y));
+        }
+        if y + 1 < self.height {
+            ns.push((x, y + 1));
+        }
+        ns
+    }
+    fn is_open(&self, c: Coord) -> bool {
+        !self.walls[Self::tile(c)]
+    }
+    // Opens both cells and the wall between them.
+    fn carve(&mut self, a: Coord, b: Coord) {
+        let (ta, tb) = (Self::tile(a), Self::tile(b));
+        self.walls[ta] = false;
+        self.walls[tb] = false;
+        self.walls[((ta.0 + tb.0) / 2, (ta.1 + tb.1) / 2)] = false;
+    }
+    fn connected(&self, a: Coord, b: Coord) -> bool {
+        let (ta, tb) = (Self::tile(a), Self::tile(b));
+        !self.walls[((ta.0 + tb.0) / 2, (ta.1 + tb.1) / 2)]
+    }
+    fn backtrack(&mut self, rng: &mut StdRng) {
+        let start = (rng.gen_range(0..self.width), rng.gen_range(0..self.height));
+        self.walls[Self::tile(start)] = false;
+        let mut stack = vec![start];
+        while let Some(&here) = stack.last() {
+            let fresh: Vec<Coord> = self
+                .neighbors(here)
+                .into_iter()
+                .filter(|&n| !self.is_open(n))
+                .collect();
+            match fresh.choose(rng) {
+                Some(&next) => {
+                    self.carve(here, next);
+                    stack.push(next);
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+    }
+    fn prim(&mut self, rng: &mut StdRng) {
+        let start = (rng.gen_range(0..self.width), rng.gen_range(0..self.height));
+        self.walls[Self::tile(start)] = false;
+        let mut frontier: Vec<(Coord, Coord)> = self
+            .neighbors(start)
+            .into_iter()
+            .map(|n| (start, n))
+            .collect();
+        while !frontier.is_empty() {
+            let (from, to) = frontier.swap_remove(rng.gen_range(0..frontier.len()));
+            if self.is_open(to) {
+                continue;
+            }
+            self.carve(from, to);
+            frontier.extend(
+                self.neighbors(to)
+                    .into_iter()
+                    .filter(|&n| !self.is_open(n))
+                    .map(|n| (to, n)),
+            );
+        }
+    }
+    // Loop-erased random walks: walk from a cell not yet in the maze until hitting the maze,
+    // remembering only the last way out of each cell, then carve the path that's left.
+    fn wilson(&mut self, rng: &mut StdRng) {
+        let mut in_maze = Grid::new(
+            self.width,
+            self.height,
+            vec![false; self.width * self.height],
+        );
+        let first = (rng.gen_range(0..self.width), rng.gen_range(0..self.height));
+        in_maze[first] = true;
+        self.walls[Self::tile(first)] = false;
+        let mut exit_dir: Grid<Option<Coord>> = Grid::new(
+            self.width,
+            self.height,
+            vec![None; self.width * self.height],
+        );
+        let cells: Vec<Coord> = self.cells().collect();
+        for start in cells {
+            if in_maze[start] {
+                continue;
+            }
+            let mut here = start;
+            while !in_maze[here] {
+                let next = *self.neighbors(here).choose(rng).unwrap();
+                exit_dir[here] = Some(next);
+                here = next;
+            }
+            let mut here = start;
+            while !in_maze[here] {
+                let next = exit_dir[here].unwrap();
+                self.carve(here, next);
+                in_maze[here] = true;
+                here = next;
+            }
+        }
+    }
+    // Knocks a wall out of some dead ends, preferring walls that lead into other dead ends.
+    fn braid(&mut self, chance: f32, rng: &mut StdRng) {
+        if chance <= 0.0 {
+            return;
+        }
+        let cells: Vec<Coord> = self.cells().collect();
+        for c in cells {
+            let ns = self.neighbors(c);
+            let closed: Vec<Coord> = ns
+                .iter()
+                .copied()
+                .filter(|&n| !self.connected(c, n))
+                .collect();
+            // still a dead end? (an earlier knock-out may have opened it up)
+            if ns.len() - closed.len() != 1 || rng.gen::<f32>() >= chance {
+                continue;
+            }
+            let dead_ends: Vec<Coord> = closed
+                .iter()
+                .copied()
+                .filter(|&n| self.passages(n) == 1)
+                .collect();
+            let pick = if dead_ends.is_empty() {
+                &closed
+            } else {
+                &dead_ends
+            };
+            if let Some(&n) = pick.choose(rng) {
+                self.carve(c, n);
+            }
+        }
+    }
+    fn passages(&self, c: Coord) -> usize {
+        self.neighbors(c)
+            .into_iter()
+            .filter(|&n| self.connected(c, n))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::GridPos;
+    const HEADER: &str = "maze 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n";
+    fn settings(algorithm: MazeAlgorithm, braid: f32) -> MazeSettings {
+        MazeSettings {
+            width: 9,
+            height: 7,
+            algorithm,
+            braid,
+            seed: 1234,
+        }
+    }
+    #[test]
+    fn test_mazes_are_perfect_and_solvable() {
+        for algorithm in [
+            MazeAlgorithm::RecursiveBacktracker,
+            MazeAlgorithm::Prim,
+            MazeAlgorithm::Wilson,
+        ] {
+            let s = settings(algorithm, 0.0);
+            let walls = generate(&s);
+            assert_eq!((walls.width(), walls.height()), (19, 15));
+            // a perfect maze is a spanning tree: every cell is open and there's one fewer passage than cells
+            let open = (0..19 * 15).filter(|&i| !walls[i]).count();
+            assert_eq!(open, 2 * 9 * 7 - 1);
+            let level = maze_level(&s, HEADER, "#", ".", 0, 0);
+            assert_eq!(level.reachable_exits(), vec![GridPos { x: 18, y: 13 }]);
+            assert!(level.pockets().is_empty());
+        }
+    }
+    #[test]
+    fn test_seed_and_braid() {
+        let a = generate(&settings(MazeAlgorithm::Wilson, 0.0));
+        let b = generate(&settings(MazeAlgorithm::Wilson, 0.0));
+        assert!((0..19 * 15).all(|i| a[i] == b[i]));
+        let braided = generate(&settings(MazeAlgorithm::RecursiveBacktracker, 1.0));
+        let open = (0..19 * 15).filter(|&i| !braided[i]).count();
+        assert!(open > 2 * 9 * 7 - 1);
+    }
+}
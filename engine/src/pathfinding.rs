@@ -0,0 +1,436 @@
+use crate::grid::{Coord, Grid};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connectivity {
+    Four,
+    Eight,
+}
+
+// An entry in the open set.  BinaryHeap is a max-heap, so we flip the
+// comparison to pop the lowest estimated total cost first.
+#[derive(Clone, Copy, Debug)]
+struct Open {
+    f: f32,
+    idx: usize,
+}
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .f
+            .total_cmp(&self.f)
+            .then_with(|| other.idx.cmp(&self.idx))
+    }
+}
+
+// `wrap` is the size of the grid if paths can go off one edge and come back on the other.
+fn heuristic(conn: Connectivity, (ax, ay): Coord, (bx, by): Coord, wrap: Option<Coord>) -> f32 {
+    let (mut dx, mut dy) = (ax.abs_diff(bx), ay.abs_diff(by));
+    if let Some((w, h)) = wrap {
+        dx = dx.min(w - dx);
+        dy = dy.min(h - dy);
+    }
+    let (dx, dy) = (dx as f32, dy as f32);
+    match conn {
+        Connectivity::Four => dx + dy,
+        // octile distance: walk diagonally as far as we can, then straight
+        Connectivity::Eight => dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy),
+    }
+}
+
+// Finds a shortest path from start to goal, including both ends.
+// `cost` says how much it costs to step onto a cell, or None if the cell can't be entered.
+// Costs should be at least 1.0 or the heuristic may overestimate and paths can come out longer than they need to be.
+// With Eight connectivity, diagonal steps cost sqrt(2) times as much and are only allowed when
+// both of the orthogonal cells beside them are passable, so paths never cut the corner of a wall.
+pub fn find_path<T>(
+    grid: &Grid<T>,
+    start: Coord,
+    goal: Coord,
+    conn: Connectivity,
+    cost: impl Fn(Coord, &T) -> Option<f32>,
+) -> Option<Vec<Coord>> {
+    search(grid, start, goal, conn, false, cost)
+}
+
+// Like find_path, but on a grid that wraps around like a torus: stepping off the left edge comes back
+// on the right, and off the top comes back on the bottom.
+pub fn find_path_wrapping<T>(
+    grid: &Grid<T>,
+    start: Coord,
+    goal: Coord,
+    conn: Connectivity,
+    cost: impl Fn(Coord, &T) -> Option<f32>,
+) -> Option<Vec<Coord>> {
+    search(grid, start, goal, conn, true, cost)
+}
+
+fn search<T>(
+    grid: &Grid<T>,
+    start: Coord,
+    goal: Coord,
+    conn: Connectivity,
+    wrap: bool,
+    cost: impl Fn(Coord, &T) -> Option<f32>,
+) -> Option<Vec<Coord>> {
+    let start_idx = grid.coord_to_index(start)?;
+    let goal_idx = grid.coord_to_index(goal)?;
+    cost(goal, &grid[goal])?;
+    let passable = |c: Coord| cost(c, &grid[c]).is_some();
+    let len = grid.width() * grid.height();
+    let size = wrap.then_some((grid.width(), grid.height()));
+    let mut g = vec![f32::INFINITY; len];
+    let mut came_from = vec![usize::MAX; len];
+    let mut closed = vec![false; len];
+    let mut open = BinaryHeap::new();
+    g[start_idx] = 0.0;
+    open.push(Open {
+        f: heuristic(conn, start, goal, size),
+        idx: start_idx,
+    });
+    while let Some(Open { idx, .. }) = open.pop() {
+        if idx == goal_idx {
+            let mut path = vec![goal];
+            let mut at = idx;
+            while at != start_idx {
+                at = came_from[at];
+                path.push(grid.index_to_coord(at).unwrap());
+            }
+            path.reverse();
+            return Some(path);
+        }
+        if closed[idx] {
+            continue;
+        }
+        closed[idx] = true;
+        let (x, y) = grid.index_to_coord(idx).unwrap();
+        for (nx, ny) in neighbors(grid, (x, y), wrap) {
+            let diagonal = nx != x && ny != y;
+            if diagonal && (conn == Connectivity::Four || !(passable((nx, y)) && passable((x, ny))))
+            {
+                continue;
+            }
+            let Some(step) = cost((nx, ny), &grid[(nx, ny)]) else {
+                continue;
+            };
+            let nidx = grid.xy_to_index(nx, ny).unwrap();
+            let step = if diagonal {
+                step * std::f32::consts::SQRT_2
+            } else {
+                step
+            };
+            let new_g = g[idx] + step;
+            if new_g < g[nidx] {
+                g[nidx] = new_g;
+                came_from[nidx] = idx;
+                open.push(Open {
+                    f: new_g + heuristic(conn, (nx, ny), goal, size),
+                    idx: nidx,
+                });
+            }
+        }
+    }
+    None
+}
+
+// The same eight cells as Grid::neighbors_8, in the same order, except that with `wrap` the ones
+// off an edge come back around from the other side instead of being left out.
+fn neighbors<T>(grid: &Grid<T>, (x, y): Coord, wrap: bool) -> impl Iterator<Item = Coord> {
+    let (w, h) = (grid.width() as isize, grid.height() as isize);
+    [
+        (-1, 0),
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ]
+    .into_iter()
+    .filter_map(move |(dx, dy)| {
+        let (nx, ny) = (x as isize + dx, y as isize + dy);
+        if wrap {
+            Some((nx.rem_euclid(w) as usize, ny.rem_euclid(h) as usize))
+        } else if (0..w).contains(&nx) && (0..h).contains(&ny) {
+            Some((nx as usize, ny as usize))
+        } else {
+            None
+        }
+    })
+}
+
+// A flow field stores, for every cell, the cost of the cheapest walk to the nearest of a set of goals.
+// Any number of agents can then steer toward the goals by looking at their own cell, instead of each
+// running find_path.  The field keeps a copy of the step costs it was built with, so call
+// update_costs if the grid changes underneath it.
+pub struct FlowField {
+    conn: Connectivity,
+    wrap: bool,
+    cost: Grid<Option<f32>>,
+    dist: Grid<f32>,
+    // which goal each cell's distance came from, so we know what to redo when a goal goes away
+    owner: Grid<Option<Coord>>,
+    goals: Vec<Coord>,
+}
+
+impl FlowField {
+    pub fn new<T>(
+        grid: &Grid<T>,
+        conn: Connectivity,
+        cost: impl Fn(Coord, &T) -> Option<f32>,
+    ) -> Self {
+        let (w, h) = (grid.width(), grid.height());
+        Self {
+            conn,
+            wrap: false,
+            cost: Grid::new(
+                w,
+                h,
+                (0..w * h).map(|idx| {
+                    let c = grid.index_to_coord(idx).unwrap();
+                    cost(c, &grid[idx])
+                }),
+            ),
+            dist: Grid::new(w, h, vec![f32::INFINITY; w * h]),
+            owner: Grid::new(w, h, vec![None; w * h]),
+            goals: vec![],
+        }
+    }
+    // Like new, but agents can walk off one edge of the grid and come back on the other (see find_path_wrapping).
+    pub fn new_wrapping<T>(
+        grid: &Grid<T>,
+        conn: Connectivity,
+        cost: impl Fn(Coord, &T) -> Option<f32>,
+    ) -> Self {
+        Self {
+            wrap: true,
+            ..Self::new(grid, conn, cost)
+        }
+    }
+    pub fn goals(&self) -> &[Coord] {
+        &self.goals
+    }
+    // Moves the goals.  Only cells whose nearest goal was removed, or that are now closer to a new goal, get recomputed.
+    pub fn set_goals(&mut self, goals: &[Coord]) {
+        let removed: Vec<Coord> = self
+            .goals
+            .iter()
+            .filter(|g| !goals.contains(g))
+            .copied()
+            .collect();
+        let mut open = BinaryHeap::new();
+        if !removed.is_empty() {
+            for idx in 0..self.dist.width() * self.dist.height() {
+                if self.owner[idx].is_some_and(|o| removed.contains(&o)) {
+                    self.dist[idx] = f32::INFINITY;
+                    self.owner[idx] = None;
+                }
+            }
+            // everything still owned by a remaining goal is correct, so grow back into the hole from its edges
+            for idx in 0..self.dist.width() * self.dist.height() {
+                if self.dist[idx].is_finite() {
+                    let (x, y) = self.dist.index_to_coord(idx).unwrap();
+                    if neighbors(&self.dist, (x, y), self.wrap).any(|n| self.dist[n].is_infinite())
+                    {
+                        open.push(Open {
+                            f: self.dist[idx],
+                            idx,
+                        });
+                    }
+                }
+            }
+        }
+        for &goal in goals {
+            if let Some(idx) = self.dist.coord_to_index(goal) {
+                if self.cost[idx].is_some() && self.dist[idx] > 0.0 {
+                    self.dist[idx] = 0.0;
+                    self.owner[idx] = Some(goal);
+                    open.push(Open { f: 0.0, idx });
+                }
+            }
+        }
+        self.goals = goals.to_vec();
+        self.propagate(open);
+    }
+    // Takes a fresh copy of the step costs (e.g. after tiles were changed) and recomputes the whole field.
+    pub fn update_costs<T>(&mut self, grid: &Grid<T>, cost: impl Fn(Coord, &T) -> Option<f32>) {
+        let goals = std::mem::take(&mut self.goals);
+        *self = Self {
+            wrap: self.wrap,
+            ..Self::new(grid, self.conn, cost)
+        };
+        self.set_goals(&goals);
+    }
+    fn propagate(&mut self, mut open: BinaryHeap<Open>) {
+        while let Some(Open { f, idx }) = open.pop() {
+            if f > self.dist[idx] {
+                continue;
+            }
+            let (x, y) = self.dist.index_to_coord(idx).unwrap();
+            // agents walk the other way, from (nx, ny) onto (x, y), so they pay the cost of this cell
+            let Some(step) = self.cost[idx] else {
+                continue;
+            };
+            for (nx, ny) in neighbors(&self.dist, (x, y), self.wrap) {
+                let Some(factor) = self.step_factor((nx, ny), (x, y)) else {
+                    continue;
+                };
+                let nidx = self.dist.xy_to_index(nx, ny).unwrap();
+                let nd = f + step * factor;
+                if nd < self.dist[nidx] {
+                    self.dist[nidx] = nd;
+                    self.owner[nidx] = self.owner[idx];
+                    open.push(Open { f: nd, idx: nidx });
+                }
+            }
+        }
+    }
+    // The distance multiplier for stepping from one cell to a neighbor, or None if that step isn't allowed.
+    fn step_factor(&self, (fx, fy): Coord, (tx, ty): Coord) -> Option<f32> {
+        let passable = |c: Coord| self.cost[c].is_some();
+        if !passable((fx, fy)) || !passable((tx, ty)) {
+            return None;
+        }
+        if fx != tx && fy != ty {
+            if self.conn == Connectivity::Four || !(passable((tx, fy)) && passable((fx, ty))) {
+                return None;
+            }
+            Some(std::f32::consts::SQRT_2)
+        } else {
+            Some(1.0)
+        }
+    }
+    // Cost of the cheapest walk from coord to a goal, or None if no goal can be reached.
+    pub fn distance(&self, coord: Coord) -> Option<f32> {
+        self.dist
+            .get(coord.0, coord.1)
+            .copied()
+            .filter(|d| d.is_finite())
+    }
+    // The neighboring cell to step onto to get closer to a goal.  None at a goal or where no goal can be reached.
+    pub fn next_cell(&self, coord: Coord) -> Option<Coord> {
+        let here = self.distance(coord)?;
+        let mut best = None;
+        let mut best_dist = here;
+        for n in neighbors(&self.dist, coord, self.wrap) {
+            let (Some(factor), Some(step)) = (self.step_factor(coord, n), self.cost[n]) else {
+                continue;
+            };
+            let d = self.dist[n] + step * factor;
+            if self.dist[n] < here && d <= best_dist {
+                best_dist = d;
+                best = Some(n);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn walls(rows: &[&str]) -> Grid<bool> {
+        let w = rows[0].len();
+        Grid::new(
+            w,
+            rows.len(),
+            rows.iter().flat_map(|r| r.chars().map(|c| c == '#')),
+        )
+    }
+    fn open(_: Coord, solid: &bool) -> Option<f32> {
+        if *solid {
+            None
+        } else {
+            Some(1.0)
+        }
+    }
+    #[test]
+    fn test_path_around_wall() {
+        let grid = walls(&["....", ".##.", ".#..", "...."]);
+        let path = find_path(&grid, (0, 0), (2, 2), Connectivity::Four, open).unwrap();
+        assert_eq!(path.first(), Some(&(0, 0)));
+        assert_eq!(path.last(), Some(&(2, 2)));
+        assert_eq!(path.len(), 7);
+        for pair in path.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+            assert!(!grid[b]);
+        }
+    }
+    #[test]
+    fn test_no_corner_cutting() {
+        let grid = walls(&["..", "#."]);
+        // going diagonally from (0,0) to (1,1) would clip the wall at (0,1)
+        let path = find_path(&grid, (0, 0), (1, 1), Connectivity::Eight, open).unwrap();
+        assert_eq!(path, vec![(0, 0), (1, 0), (1, 1)]);
+        let grid = walls(&["..", ".."]);
+        let path = find_path(&grid, (0, 0), (1, 1), Connectivity::Eight, open).unwrap();
+        assert_eq!(path, vec![(0, 0), (1, 1)]);
+    }
+    #[test]
+    fn test_flow_field() {
+        let grid = walls(&["....", ".##.", ".#..", "...."]);
+        let mut field = FlowField::new(&grid, Connectivity::Four, open);
+        field.set_goals(&[(2, 2)]);
+        // following the field walks the same length as the A* path
+        let mut at = (0, 0);
+        let mut steps = 0;
+        while let Some(next) = field.next_cell(at) {
+            assert!(!grid[next]);
+            at = next;
+            steps += 1;
+        }
+        assert_eq!(at, (2, 2));
+        assert_eq!(steps, 6);
+        assert_eq!(field.distance((0, 0)), Some(6.0));
+        assert_eq!(field.distance((1, 1)), None);
+        // moving the goal only has to redo part of the field, but should match a fresh one
+        field.set_goals(&[(0, 3), (3, 0)]);
+        let mut fresh = FlowField::new(&grid, Connectivity::Four, open);
+        fresh.set_goals(&[(0, 3), (3, 0)]);
+        for y in 0..4 {
+            for x in 0..4 {
+                assert_eq!(field.distance((x, y)), fresh.distance((x, y)));
+            }
+        }
+        assert_eq!(field.next_cell((3, 0)), None);
+    }
+    #[test]
+    fn test_unreachable() {
+        let grid = walls(&[".#.", ".#.", ".#."]);
+        assert_eq!(
+            find_path(&grid, (0, 0), (2, 2), Connectivity::Eight, open),
+            None
+        );
+        assert_eq!(
+            find_path(&grid, (0, 0), (1, 1), Connectivity::Four, open),
+            None
+        );
+    }
+    #[test]
+    fn test_wrapping() {
+        // the wall down the middle has no gap, but the left and right edges meet
+        let grid = walls(&[".#..", ".#..", ".#.."]);
+        let path = find_path_wrapping(&grid, (0, 1), (3, 1), Connectivity::Four, open).unwrap();
+        assert_eq!(path, vec![(0, 1), (3, 1)]);
+        let path = find_path(&grid, (0, 1), (3, 1), Connectivity::Four, open);
+        assert_eq!(path, None);
+        let mut field = FlowField::new_wrapping(&grid, Connectivity::Four, open);
+        field.set_goals(&[(2, 0)]);
+        assert_eq!(field.distance((0, 0)), Some(2.0));
+        assert_eq!(field.next_cell((0, 0)), Some((3, 0)));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::geom::{Rect, Vec2, WorldPos};
+use crate::level::Level;
+
+/*
+A kinematic body moves something around the level with some momentum instead of setting its position directly.
+Each tick, call accelerate with where it's trying to go (e.g. from the arrow keys or toward its next waypoint),
+impulse for sudden shoves like knockback, and then step once with the fixed tick's dt.
+
+Friction always pulls the body's speed down toward zero, scaled by the friction of the tile under it, so it
+slides to a stop on ordinary ground and keeps gliding on ice.  Accelerating can't take it past max_speed, but
+an impulse can, and then friction brings it back down.  Bodies stop at solid tiles and slide along them:
+hitting a wall only cancels the part of the velocity going into the wall.  Moves are swept rather than checked
+at the end, so even a knockback fast enough to cross several tiles in one step stops at the first wall.
+*/
+
+// How many walls one move can slide off of before giving up on the rest of it (e.g. when wedged into a corner).
+const SLIDES: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Kinematic {
+    // pixels per second
+    pub vel: Vec2,
+    pub max_speed: f32,
+    // how much speed it loses per second on a tile with friction 1.0
+    pub friction: f32,
+    // acceleration asked for since the last step
+    accel: Vec2,
+}
+
+// A body at rest with no speed limit and no friction, which only moves when something pushes it.
+impl Default for Kinematic {
+    fn default() -> Self {
+        Self::new(f32::INFINITY, 0.0)
+    }
+}
+
+impl Kinematic {
+    pub fn new(max_speed: f32, friction: f32) -> Self {
+        Self {
+            vel: Vec2 { x: 0.0, y: 0.0 },
+            max_speed,
+            friction,
+            accel: Vec2 { x: 0.0, y: 0.0 },
+        }
+    }
+    // Pixels per second per second, applied over the next step.
+    pub fn accelerate(&mut self, accel: Vec2) {
+        self.accel += accel;
+    }
+    // Changes the velocity right away, and isn't held back by max_speed.
+    pub fn impulse(&mut self, dv: Vec2) {
+        self.vel += dv;
+    }
+    pub fn stop(&mut self) {
+        self.vel = Vec2 { x: 0.0, y: 0.0 };
+        self.accel = Vec2 { x: 0.0, y: 0.0 };
+    }
+    pub fn speed(&self) -> f32 {
+        self.vel.mag_sq().sqrt()
+    }
+    // Moves a size by size body centered on pos for dt seconds, stopping at the level's solid tiles.
+    pub fn step(&mut self, pos: &mut WorldPos, size: u16, level: &Level, dt: f32) {
+        let grip = level.get_tile_at(*pos).friction;
+        self.vel = with_speed(self.vel, self.speed() - self.friction * grip * dt);
+        let before = self.speed();
+        self.vel += self.accel * dt;
+        self.accel = Vec2 { x: 0.0, y: 0.0 };
+        // whatever speed it already had from an impulse it can keep, but accelerating won't add to it
+        self.vel = with_speed(self.vel, self.speed().min(self.max_speed.max(before)));
+        for normal in move_and_slide(pos, size, self.vel * dt, level) {
+            let into = self.vel.dot(normal);
+            if into < 0.0 {
+                self.vel += normal * -into;
+            }
+        }
+        *pos = level.wrap_pos(*pos);
+    }
+}
+
+// v scaled to the given speed, or zero if that's not positive.
+fn with_speed(v: Vec2, speed: f32) -> Vec2 {
+    let mag = v.mag_sq().sqrt();
+    if speed <= 0.0 || mag == 0.0 {
+        Vec2 { x: 0.0, y: 0.0 }
+    } else {
+        v * (speed / mag)
+    }
+}
+
+// Moves a size by size body centered on pos by delta, stopping where it first touches a solid tile and then
+// sliding the rest of the way along it.  Returns the normals of the walls it hit, in order.
+pub fn move_and_slide(pos: &mut WorldPos, size: u16, delta: Vec2, level: &Level) -> Vec<Vec2> {
+    let half = size as f32 / 2.0;
+    let mut left = delta;
+    let mut normals = vec![];
+    for _ in 0..SLIDES {
+        if left.mag_sq() == 0.0 {
+            break;
+        }
+        let body = Rect {
+            x: pos.x - half,
+            y: pos.y - half,
+            w: size,
+            h: size,
+        };
+        let Some(hit) = level.sweep(body, left) else {
+            *pos += left;
+            return normals;
+        };
+        *pos += left * hit.time;
+        // whatever's left of the move, minus the part going into the wall
+        let rest = left * (1.0 - hit.time);
+        left = rest + hit.normal * -rest.dot(hit.normal);
+        normals.push(hit.normal);
+    }
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // a room with a wall along its right side and a strip of ice along the bottom
+    const ROOM: &str = "room 6 4 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n~ i 32 0 16 16\n====\n. . . . . #\n. . . . . #\n. . . . . #\n~ ~ ~ ~ ~ #\n";
+    const DT: f32 = 1.0 / 60.0;
+    #[test]
+    fn test_accelerate_and_coast() {
+        let level = Level::from_str(ROOM, 0, 0);
+        let mut body = Kinematic::new(64.0, 256.0);
+        let mut pos = WorldPos { x: 8.0, y: 40.0 };
+        for _ in 0..30 {
+            body.accelerate(Vec2 { x: 1024.0, y: 0.0 });
+            body.step(&mut pos, 16, &level, DT);
+        }
+        assert_eq!(body.speed(), 64.0);
+        // let go and it slides to a stop in about a quarter of a second
+        for _ in 0..16 {
+            body.step(&mut pos, 16, &level, DT);
+        }
+        assert_eq!(body.speed(), 0.0);
+        // the same push on ice keeps going a lot longer
+        let mut pos = WorldPos { x: 8.0, y: 8.0 };
+        body.impulse(Vec2 { x: 64.0, y: 0.0 });
+        for _ in 0..15 {
+            body.step(&mut pos, 16, &level, DT);
+        }
+        assert!(body.speed() > 50.0);
+    }
+    #[test]
+    fn test_impulse_beats_max_speed() {
+        let level = Level::from_str(ROOM, 0, 0);
+        let mut body = Kinematic::new(64.0, 256.0);
+        let mut pos = WorldPos { x: 8.0, y: 40.0 };
+        body.impulse(Vec2 { x: 0.0, y: 200.0 });
+        body.accelerate(Vec2 { x: 0.0, y: 1024.0 });
+        body.step(&mut pos, 16, &level, DT);
+        assert!(body.speed() > 64.0 && body.speed() <= 200.0);
+    }
+    #[test]
+    fn test_slide_along_wall() {
+        let level = Level::from_str(ROOM, 0, 0);
+        let mut body = Kinematic::new(64.0, 0.0);
+        let mut pos = WorldPos { x: 70.0, y: 24.0 };
+        body.vel = Vec2 { x: 64.0, y: 32.0 };
+        body.step(&mut pos, 16, &level, 0.5);
+        // stopped flush against the wall at x=80, but still moved up
+        assert_eq!(pos, WorldPos { x: 72.0, y: 40.0 });
+        assert_eq!(body.vel, Vec2 { x: 0.0, y: 32.0 });
+    }
+    #[test]
+    fn test_no_tunneling() {
+        // a single tile thick wall down the middle, with open floor on both sides
+        let level = Level::from_str(
+            "wall 7 3 16\n====\n. o 0 0 16 16\n# s 16 0 16 16\n====\n. . . # . . .\n. . . # . . .\n. . . # . . .\n",
+            0,
+            0,
+        );
+        // fast enough to jump clean over the wall between one tick and the next
+        let mut body = Kinematic::default();
+        let mut pos = WorldPos { x: 8.0, y: 24.0 };
+        body.impulse(Vec2 { x: 4800.0, y: 0.0 });
+        body.step(&mut pos, 8, &level, DT);
+        assert_eq!(pos, WorldPos { x: 44.0, y: 24.0 });
+        assert_eq!(body.vel, Vec2 { x: 0.0, y: 0.0 });
+        // coming in at an angle it slides along the wall instead, and doesn't snag on the seams between tiles
+        let mut pos = WorldPos { x: 36.0, y: 8.0 };
+        let normals = move_and_slide(&mut pos, 8, Vec2 { x: 16.0, y: 24.0 }, &level);
+        assert_eq!(normals, vec![Vec2 { x: -1.0, y: 0.0 }]);
+        assert_eq!(pos, WorldPos { x: 44.0, y: 32.0 });
+        // and however fast it goes, it still stops at the nearest wall (here the edge of the level)
+        move_and_slide(
+            &mut pos,
+            8,
+            Vec2 {
+                x: -100_000.0,
+                y: 0.0,
+            },
+            &level,
+        );
+        assert_eq!(pos, WorldPos { x: 4.0, y: 32.0 });
+    }
+}
@@ -0,0 +1,166 @@
+use crate::geom::Vec2;
+use crate::grid::{Coord, Grid};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    // the blocking cell the ray ran into
+    pub cell: Coord,
+    // how far along the ray the hit happened
+    pub distance: f32,
+    // where the ray crossed into the cell
+    pub point: Vec2,
+    // the outward normal of the face that was hit (e.g. x=-1 for the left face)
+    pub normal: Vec2,
+}
+
+// Walks a ray through the grid one cell boundary at a time (Amanatides & Woo's DDA) and reports the first
+// cell that `blocks`.  Positions here are in grid units, where cell (x, y) covers x..x+1 and y..y+1
+// and y grows downwards like grid rows do.  The cell the ray starts in is never reported, so an
+// entity overlapping a wall can still look out of it.  Returns None if the ray leaves the grid or
+// travels max_dist without hitting anything.
+pub fn cast<T>(
+    grid: &Grid<T>,
+    origin: Vec2,
+    dir: Vec2,
+    max_dist: f32,
+    blocks: impl Fn(Coord, &T) -> bool,
+) -> Option<RayHit> {
+    if dir.mag_sq() == 0.0 {
+        return None;
+    }
+    let dir = dir.normalize();
+    let mut cx = origin.x.floor() as isize;
+    let mut cy = origin.y.floor() as isize;
+    let step_x = if dir.x > 0.0 { 1 } else { -1 };
+    let step_y = if dir.y > 0.0 { 1 } else { -1 };
+    // how far we travel to cross one whole cell in each axis
+    let delta_x = (1.0 / dir.x).abs();
+    let delta_y = (1.0 / dir.y).abs();
+    // how far we travel to reach the next boundary in each axis
+    let mut next_x = if dir.x > 0.0 {
+        (cx as f32 + 1.0 - origin.x) * delta_x
+    } else {
+        (origin.x - cx as f32) * delta_x
+    };
+    let mut next_y = if dir.y > 0.0 {
+        (cy as f32 + 1.0 - origin.y) * delta_y
+    } else {
+        (origin.y - cy as f32) * delta_y
+    };
+    loop {
+        let (distance, normal) = if next_x < next_y {
+            cx += step_x;
+            next_x += delta_x;
+            (
+                next_x - delta_x,
+                Vec2 {
+                    x: -step_x as f32,
+                    y: 0.0,
+                },
+            )
+        } else {
+            cy += step_y;
+            next_y += delta_y;
+            (
+                next_y - delta_y,
+                Vec2 {
+                    x: 0.0,
+                    y: -step_y as f32,
+                },
+            )
+        };
+        if distance > max_dist || cx < 0 || cy < 0 {
+            return None;
+        }
+        let cell = (cx as usize, cy as usize);
+        let tile = grid.get(cell.0, cell.1)?;
+        if blocks(cell, tile) {
+            return Some(RayHit {
+                cell,
+                distance,
+                point: origin + dir * distance,
+                normal,
+            });
+        }
+    }
+}
+
+// Whether the straight line between the centers of two cells is clear of blocking cells.
+// The two end cells themselves don't count, so a wall can see its neighbor.
+pub fn has_line_of_sight<T>(
+    grid: &Grid<T>,
+    a: Coord,
+    b: Coord,
+    blocks: impl Fn(Coord, &T) -> bool,
+) -> bool {
+    if a == b {
+        return true;
+    }
+    let center = |(x, y): Coord| Vec2 {
+        x: x as f32 + 0.5,
+        y: y as f32 + 0.5,
+    };
+    let (from, to) = (center(a), center(b));
+    let dir = to + from * -1.0;
+    match cast(grid, from, dir, dir.mag_sq().sqrt(), blocks) {
+        Some(hit) => hit.cell == b,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn walls(rows: &[&str]) -> Grid<bool> {
+        Grid::new(
+            rows[0].len(),
+            rows.len(),
+            rows.iter().flat_map(|r| r.chars().map(|c| c == '#')),
+        )
+    }
+    #[test]
+    fn test_cast_hits_wall_face() {
+        let grid = walls(&["....#", ".....", "....."]);
+        let hit = cast(
+            &grid,
+            Vec2 { x: 0.5, y: 0.5 },
+            Vec2 { x: 1.0, y: 0.0 },
+            10.0,
+            |_, s| *s,
+        )
+        .unwrap();
+        assert_eq!(hit.cell, (4, 0));
+        assert_eq!(hit.distance, 3.5);
+        assert_eq!(hit.point, Vec2 { x: 4.0, y: 0.5 });
+        assert_eq!(hit.normal, Vec2 { x: -1.0, y: 0.0 });
+        // too short to reach the wall, and a ray that leaves the grid
+        assert_eq!(
+            cast(
+                &grid,
+                Vec2 { x: 0.5, y: 0.5 },
+                Vec2 { x: 1.0, y: 0.0 },
+                3.0,
+                |_, s| *s
+            ),
+            None
+        );
+        assert_eq!(
+            cast(
+                &grid,
+                Vec2 { x: 0.5, y: 1.5 },
+                Vec2 { x: 1.0, y: 0.0 },
+                10.0,
+                |_, s| *s
+            ),
+            None
+        );
+    }
+    #[test]
+    fn test_line_of_sight() {
+        let grid = walls(&["...", ".#.", "..."]);
+        assert!(!has_line_of_sight(&grid, (0, 1), (2, 1), |_, s| *s));
+        assert!(has_line_of_sight(&grid, (0, 0), (2, 0), |_, s| *s));
+        assert!(has_line_of_sight(&grid, (0, 1), (1, 1), |_, s| *s));
+        assert!(!has_line_of_sight(&grid, (0, 0), (2, 2), |_, s| *s));
+    }
+}
@@ -3,7 +3,8 @@ use frenderer::{
     input::{Input, Key}, sprites::{Camera2D, SheetRegion, Transform}, wgpu, Immediate
 };
 use engine::level::Level;
-use engine::Contact;
+use engine::maze::{self, MazeAlgorithm, MazeSettings};
+use engine::physics::Kinematic;
 use engine::Dir;
 use engine::Pos;
 use engine::{geom::*, World};
@@ -12,17 +13,40 @@ use std::io;
 
 const PLAYER: SheetRegion = SheetRegion::rect(0, 579, 16, 16);
 
-const TILE_SZ: usize = 16;
+// size of HUD and menu sprites; things in the world are as big as the current level's tiles
+const UI_SZ: usize = 16;
 const W: usize = 220; // 320
 const H: usize = 140; // 240
 const SCREEN_FAST_MARGIN: f32 = 64.0;
 
 // pixels per second
 const PLAYER_SPEED: f32 = 64.0;
-const _KNOCKBACK_SPEED: f32 = 128.0;
+// pixels per second per second: how quickly the player gets up to speed and slows down again
+const PLAYER_ACCEL: f32 = 1024.0;
+const PLAYER_FRICTION: f32 = 512.0;
+// how many tiles the torch lights up in torchlight mode
+const TORCH_RADIUS: usize = 3;
 
 const DT: f32 = 1.0 / 60.0;
 
+// Every round after the first gets a freshly generated maze with the same tiles as maze0.txt.
+// Set MAZE_SEED to replay the same sequence of mazes; without it every run picks a new random seed and its
+// mazes can't be played again.
+const MAZE_HEADER: &str = "generated 16
+bg 85 17 2 2
+par=45
+====
+. o 85 17 16 16
+# s 136 306 16 16
+";
+const MAZE_CELLS: (usize, usize) = (13, 13);
+const MAZE_BRAID: f32 = 0.1;
+const MAZE_ALGORITHMS: [MazeAlgorithm; 3] = [
+    MazeAlgorithm::RecursiveBacktracker,
+    MazeAlgorithm::Prim,
+    MazeAlgorithm::Wilson,
+];
+
 fn main() {
     #[cfg(not(target_arch = "wasm32"))]
     let source =
@@ -36,13 +60,34 @@ fn main() {
 
 struct MazeGame {
     stopwatch: Stopwatch,
+    // only show the maze near the player, and dimly where they've already been
+    torchlight: bool,
+    // the seed of the first generated maze; round n uses seed + n
+    seed: u64,
+    round: u64,
+    // open border tiles other than the start: walking onto one finishes the round
+    exits: Vec<GridPos>,
+    // whether this round's time has gone on the leaderboard yet
+    recorded: bool,
+    // seconds to beat, from the maze's `par` metadata
+    par: Option<u32>,
     leaderboard: Vec<(String, String)>, // TODO: have this instead be something that lives in the file (not new with each game)
 }
 
 impl MazeGame {
     fn new(world: &mut World) -> Self {
+        let seed = std::env::var("MAZE_SEED")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(rand::random);
         let game = MazeGame {
             stopwatch: Stopwatch::start_new(),
+            torchlight: false,
+            seed,
+            round: 0,
+            exits: world.level().reachable_exits(),
+            recorded: false,
+            par: par_time(world.level()),
             leaderboard: vec![],
         };
         let player_start = *world.levels[world.current_level]
@@ -55,10 +100,37 @@ impl MazeGame {
         game
     }
 
+    fn new_round(&mut self, world: &mut World) {
+        self.round += 1;
+        let settings = MazeSettings {
+            width: MAZE_CELLS.0,
+            height: MAZE_CELLS.1,
+            algorithm: MAZE_ALGORITHMS[self.round as usize % MAZE_ALGORITHMS.len()],
+            braid: MAZE_BRAID,
+            seed: self.seed.wrapping_add(self.round),
+        };
+        let mut level = maze::maze_level(&settings, MAZE_HEADER, "#", ".", 0, 0);
+        if self.torchlight {
+            level.enable_fog();
+        }
+        let player_start = level
+            .player_start()
+            .expect("Generated maze doesn't put the player anywhere");
+        self.exits = level.reachable_exits();
+        self.par = par_time(&level);
+        world.levels[world.current_level] = level;
+        world.enter_level(player_start);
+        world.camera.screen_pos = [0.0, 0.0];
+        world.paused = false;
+        world.game_end = false;
+        self.recorded = false;
+        self.stopwatch.restart();
+    }
+
     fn draw_hud(&self, frend: &mut Immediate) {
         let timer_pos = Transform {
-            w: TILE_SZ as u16, 
-            h: TILE_SZ as u16,
+            w: UI_SZ as u16, 
+            h: UI_SZ as u16,
             x: 6.0,
             y: 10.0,
             rot: 0.0,
@@ -85,9 +157,12 @@ impl MazeGame {
             milliseconds = timer[0..timer.len()].to_string();
         }
 
-        let timer_str = format!("{}:{}", seconds, milliseconds);
+        let mut timer_str = format!("{}:{}", seconds, milliseconds);
+        if let Some(par) = self.par {
+            timer_str.push_str(&format!(" par {par}"));
+        }
 
-        frend.draw_text(1, &font, &timer_str, [timer_pos.x, timer_pos.y], 0, (TILE_SZ/2) as f32);
+        frend.draw_text(1, &font, &timer_str, [timer_pos.x, timer_pos.y], 0, (UI_SZ/2) as f32);
     }
 
     fn draw_leaderboard(&mut self, frend: &mut Immediate, world: &mut World) {
@@ -117,33 +192,35 @@ impl MazeGame {
             },
         );
 
-        let pause_x = W as f32/2.0 - 4.0*TILE_SZ as f32; 
-        let pause_y = H as f32/2.0 - 3.0*TILE_SZ as f32; 
+        let pause_x = W as f32/2.0 - 4.0*UI_SZ as f32; 
+        let pause_y = H as f32/2.0 - 3.0*UI_SZ as f32; 
         
         // game end, draw leaderboard
         world.paused = true;
-        frend.draw_nineslice(1, &nine_tiled, pause_x, pause_y, 8.0*TILE_SZ as f32, 6.0*TILE_SZ as f32, 0);  
+        frend.draw_nineslice(1, &nine_tiled, pause_x, pause_y, 8.0*UI_SZ as f32, 6.0*UI_SZ as f32, 0);  
 
-        let name: String = get_user_input();
-            
-        let timer = self.stopwatch.elapsed().as_millis().to_string();
-        let seconds;
-        let milliseconds;
+        if !self.recorded {
+            let name: String = get_user_input();
 
-        if timer.len() >= 3 {
-            seconds = timer[0..timer.len()-3].to_string();
-            milliseconds = timer[timer.len()-3..timer.len()].to_string();
-        }
-        else {
-            seconds = "0".to_string();
-            milliseconds = timer[0..timer.len()].to_string();
-        }
+            let timer = self.stopwatch.elapsed().as_millis().to_string();
+            let seconds;
+            let milliseconds;
 
-        let timer_str = format!("{}:{}", seconds, milliseconds);
-        //dbg!(timer_str);
+            if timer.len() >= 3 {
+                seconds = timer[0..timer.len() - 3].to_string();
+                milliseconds = timer[timer.len() - 3..timer.len()].to_string();
+            } else {
+                seconds = "0".to_string();
+                milliseconds = timer[0..timer.len()].to_string();
+            }
+
+            let timer_str = format!("{}:{}", seconds, milliseconds);
+            //dbg!(timer_str);
 
-        self.leaderboard.push((name, timer_str));
-        self.leaderboard.sort_by(|a, b| a.1.cmp(&b.1));
+            self.leaderboard.push((name, timer_str));
+            self.leaderboard.sort_by(|a, b| a.1.cmp(&b.1));
+            self.recorded = true;
+        }
 
         let font = frenderer::bitfont::BitFont::with_sheet_region(
             ' '..='ÿ', 
@@ -153,7 +230,7 @@ impl MazeGame {
             (1) as u16, 
             (2) as u16);
                 
-        frend.draw_text(1, &font, "leaderboard", [(W/2) as f32 - 3.0*TILE_SZ as f32, (H/2) as f32], 0, (TILE_SZ/2) as f32);
+        frend.draw_text(1, &font, "leaderboard", [(W/2) as f32 - 3.0*UI_SZ as f32, (H/2) as f32], 0, (UI_SZ/2) as f32);
 
         let max;
         if self.leaderboard.len() > 3 {
@@ -164,11 +241,12 @@ impl MazeGame {
 
         for i in 0..max {
             let text = format!("{}: {}", self.leaderboard[i].0, self.leaderboard[i].1);
-            frend.draw_text(1, &font, &text, [(W/2) as f32 - 3.0*TILE_SZ as f32, (H/2) as f32 + TILE_SZ as f32 + 10.0], 0, (TILE_SZ/2) as f32);
+            frend.draw_text(1, &font, &text, [(W/2) as f32 - 3.0*UI_SZ as f32, (H/2) as f32 + UI_SZ as f32 + 10.0], 0, (UI_SZ/2) as f32);
         }
     }  
 
     fn simulate(&mut self, world: &mut World, input: &Input, _dt: f32) {
+        let tsz = world.level().tile_size();
         if world.paused {
             self.stopwatch.stop();
         }
@@ -182,10 +260,16 @@ impl MazeGame {
             world.paused = true;
             world.game_end = true;
         }
+        if world.game_end {
+            if input.is_key_pressed(Key::KeyN) {
+                self.new_round(world);
+            }
+            return;
+        }
 
-        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight) * PLAYER_SPEED * DT;
+        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight);
         // now down means -y and up means +y!  beware!
-        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp) * PLAYER_SPEED * DT;
+        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp);
         if dx > 0.0 {
             world.player.dir = Dir::E;
         }
@@ -198,66 +282,55 @@ impl MazeGame {
         if dy < 0.0 {
             world.player.dir = Dir::S;
         }
-        let dest = world.player.pos + Vec2 { x: dx, y: dy };
-        if !world.level().get_tile_at(dest).unwrap().solid { // switch to map whether it is solid then use unwrap or default
-            world.player.pos = dest;
+        world
+            .player
+            .motion
+            .accelerate(Vec2 { x: dx, y: dy } * PLAYER_ACCEL);
+        let level = &world.levels[world.current_level];
+        world
+            .player
+            .motion
+            .step(&mut world.player.pos, tsz as u16, level, DT);
+        if input.is_key_pressed(Key::KeyT) {
+            self.torchlight = !self.torchlight;
+            let level = &mut world.levels[world.current_level];
+            if self.torchlight {
+                level.enable_fog();
+            } else {
+                level.disable_fog();
+            }
+        }
+        let player_pos = world.player.pos;
+        world.levels[world.current_level].update_fog(player_pos, TORCH_RADIUS);
+        if self
+            .exits
+            .contains(&world.level().world_to_grid(player_pos))
+        {
+            world.paused = true;
+            world.game_end = true;
         }
 
         let lw = world.level().width();
         let lh = world.level().height();
 
-        while world.player.pos.x
-            > world.camera.screen_pos[0] + world.camera.screen_size[0] - SCREEN_FAST_MARGIN
-        {
+        // scroll until the player is at least SCREEN_FAST_MARGIN away from the edges of the view
+        let on_screen = |world: &World| world.camera.to_screen(world.player.pos);
+        while on_screen(world).x > world.camera.screen_size[0] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] += 1.0;
         }
-        while world.player.pos.x < world.camera.screen_pos[0] + SCREEN_FAST_MARGIN {
+        while on_screen(world).x < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] -= 1.0;
         }
-        while world.player.pos.y
-            > world.camera.screen_pos[1] + world.camera.screen_size[1] - SCREEN_FAST_MARGIN
-        {
+        while on_screen(world).y > world.camera.screen_size[1] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] += 1.0;
         }
-        while world.player.pos.y < world.camera.screen_pos[1] + SCREEN_FAST_MARGIN {
+        while on_screen(world).y < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] -= 1.0;
         }
         world.camera.screen_pos[0] =
-            world.camera.screen_pos[0].clamp(0.0, (lw * TILE_SZ).max(W) as f32 - W as f32);
+            world.camera.screen_pos[0].clamp(0.0, (lw * tsz).max(W) as f32 - W as f32);
         world.camera.screen_pos[1] =
-            world.camera.screen_pos[1].clamp(0.0, (lh * TILE_SZ).max(H) as f32 - H as f32);
-
-        let mut contacts: Vec<Contact> = Vec::new();
-        let p_rect = Rect {
-            x: world.player.pos.x - (TILE_SZ / 2) as f32,
-            y: world.player.pos.y - (TILE_SZ / 2) as f32,
-            w: (TILE_SZ) as u16,
-            h: (TILE_SZ) as u16,
-        };
-        let player = [p_rect, Rect {x: 0.0, y: 0.0, w: 0, h: 0}];
-
-        // Tile and Player contacts
-        let mut tile_contacts = Vec::new();
-        generate_tile_contact(&[player[0]], world.level(), &mut tile_contacts);
-
-        // Contact Resolution for player vs. world
-        tile_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_contacts {
-            world.player.pos += find_displacement(p_rect, contact.b_rect);
-        }
-
-        // For deleting enemies, it's best to add the enemy to a "to_remove" vec, and then remove those enemies after this loop is all done.
-        contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        }); 
+            world.camera.screen_pos[1].clamp(0.0, (lh * tsz).max(H) as f32 - H as f32);
     }
 }
 
@@ -266,10 +339,11 @@ impl engine::Game for MazeGame {
         self.simulate(world, input, DT);
     }
     fn render(&mut self, world: &mut engine::World, frend: &mut Immediate) {
+        let tsz = world.level().tile_size();
         // make this exactly as big as we need
         frend.sprite_group_set_camera(0, world.camera);
 
-        world.level().render_immediate(frend);
+        world.level().render_visible(frend, &world.camera);
 
         self.draw_hud(frend);
 
@@ -280,8 +354,8 @@ impl engine::Game for MazeGame {
         frend.draw_sprite(
             0,
             Transform {
-                w: TILE_SZ as u16,
-                h: TILE_SZ as u16,
+                w: tsz as u16,
+                h: tsz as u16,
                 x: world.player.pos.x,
                 y: world.player.pos.y,
                 rot: 0.0,
@@ -294,8 +368,8 @@ impl engine::Game for MazeGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
+                    w: tsz as u16,
+                    h: tsz as u16,
                     x: world.player.pos.x,
                     y: world.player.pos.y,
                     rot: 0.0,
@@ -330,15 +404,15 @@ impl engine::Game for MazeGame {
                     repeat: frenderer::nineslice::Repeat::Tile,
                 },
             );
-            let pause_x = W as f32 / 2.0 - 4.0 * TILE_SZ as f32;
-            let pause_y = H as f32 / 2.0 - 3.0 * TILE_SZ as f32;
+            let pause_x = W as f32 / 2.0 - 4.0 * UI_SZ as f32;
+            let pause_y = H as f32 / 2.0 - 3.0 * UI_SZ as f32;
             frend.draw_nineslice(
                 1,
                 &nine_tiled,
                 pause_x,
                 pause_y,
-                8.0 * TILE_SZ as f32,
-                6.0 * TILE_SZ as f32,
+                8.0 * UI_SZ as f32,
+                6.0 * UI_SZ as f32,
                 0,
             );
 
@@ -358,11 +432,11 @@ impl engine::Game for MazeGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 3.0 * TILE_SZ as f32,
-                    (H / 2) as f32 + TILE_SZ as f32,
+                    (W / 2) as f32 - 3.0 * UI_SZ as f32,
+                    (H / 2) as f32 + UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
             );
             text = "unpause: Esc";
             frend.draw_text(
@@ -370,12 +444,38 @@ impl engine::Game for MazeGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 3.25 * TILE_SZ as f32,
-                    (H / 2) as f32 - TILE_SZ as f32,
+                    (W / 2) as f32 - 3.25 * UI_SZ as f32,
+                    (H / 2) as f32 - UI_SZ as f32,
+                ],
+                0,
+                (UI_SZ / 2) as f32,
+            );
+            text = "torchlight: T";
+            frend.draw_text(
+                1,
+                &font,
+                text,
+                [
+                    (W / 2) as f32 - 3.25 * UI_SZ as f32,
+                    (H / 2) as f32 - 2.0 * UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
             );
+            if world.game_end {
+                text = "new maze: N";
+                frend.draw_text(
+                    1,
+                    &font,
+                    text,
+                    [
+                        (W / 2) as f32 - 3.25 * UI_SZ as f32,
+                        (H / 2) as f32 - 3.0 * UI_SZ as f32,
+                    ],
+                    0,
+                    (UI_SZ / 2) as f32,
+                );
+            }
         }
     }
     fn new(renderer: &mut Immediate, cache: AssetCache, world: &mut engine::World) -> Self {
@@ -406,8 +506,8 @@ impl engine::Game for MazeGame {
             screen_pos: [0.0, 0.0],
             screen_size: [W as f32, H as f32],
         };
-        let sprite_estimate =
-            levels[current_level].sprite_count() + levels[current_level].starts().len();
+        let sprite_estimate = levels[current_level].sprite_count_for_view(&camera)
+            + levels[current_level].starts().len();
         // tile sprite group: 0
         renderer.sprite_group_add(&tile_tex, sprite_estimate, camera);
         // HUD sprite group: 1
@@ -425,12 +525,20 @@ impl engine::Game for MazeGame {
         world.set_player(Pos {
             pos: player_start,
             dir: Dir::S,
+            motion: Kinematic::new(PLAYER_SPEED, PLAYER_FRICTION),
         });
         MazeGame::new(world)
     }
 }
 
 
+// The maze's par time in seconds, if it has one.
+fn par_time(level: &Level) -> Option<u32> {
+    level
+        .has_meta("par")
+        .then(|| level.meta("par").unwrap_or_else(|e| panic!("{e}")))
+}
+
 pub fn get_user_input() -> String {
     let mut input = String::new();
 
@@ -440,38 +548,3 @@ pub fn get_user_input() -> String {
 
     input.trim().to_string()
 }
-
-fn generate_tile_contact(group_a: &[Rect], lvl: &Level, contacts: &mut Vec<Contact>) {
-    for (a_i, a_rect) in group_a.iter().enumerate() {
-        for (_, b_rect, _) in lvl.tiles_within(*a_rect).filter(|(_, _r, td)| td.solid) {
-            if let Some(overlap) = a_rect.overlap(b_rect) {
-                contacts.push(Contact {
-                    displacement: overlap,
-                    a_index: a_i,
-                    _a_rect: *a_rect,
-                    b_index: 0,
-                    b_rect,
-                });
-            }
-        }
-    }
-}
-
-fn find_displacement(a: Rect, b: Rect) -> Vec2 {
-    if let Some(mut overlap) = a.overlap(b) {
-        if overlap.x < overlap.y {
-            overlap.y = 0.0;
-        } else {
-            overlap.x = 0.0;
-        }
-        if a.x < b.x {
-            overlap.x *= -1.0;
-        }
-        if a.y < b.y {
-            overlap.y *= -1.0;
-        }
-        overlap
-    } else {
-        Vec2 { x: 0.0, y: 0.0 }
-    }
-}
\ No newline at end of file
@@ -1,5 +1,9 @@
 use assets_manager::{asset::Png, AssetCache};
-use engine::level::Level;
+use engine::cave::{self, CaveSettings};
+use engine::collision::{Body, Priority};
+use engine::level::OutOfBounds;
+use engine::pathfinding::{Connectivity, FlowField};
+use engine::physics::Kinematic;
 use engine::Contact;
 use engine::Dir;
 use engine::Pos;
@@ -15,7 +19,8 @@ const PLAYER: SheetRegion = SheetRegion::rect(51, 0, 16, 16);
 const KNIGHT1: SheetRegion = SheetRegion::rect(119, 17, 16, 16);
 const ENEMY: SheetRegion = SheetRegion::rect(17, 0, 16, 16);
 
-const TILE_SZ: usize = 16;
+// size of HUD and menu sprites; things in the world are as big as the current level's tiles
+const UI_SZ: usize = 16;
 const W: usize = 516; // 320
 const H: usize = 240; // 240
 const SCREEN_FAST_MARGIN: f32 = 64.0;
@@ -23,9 +28,37 @@ const SCREEN_FAST_MARGIN: f32 = 64.0;
 // pixels per second
 const PLAYER_SPEED: f32 = 64.0;
 const ENEMY_SPEED: f32 = 32.0;
+// pixels per second per second: how quickly things get up to speed and how quickly they slow down
+const PLAYER_ACCEL: f32 = 1024.0;
+const PLAYER_FRICTION: f32 = 512.0;
+const ENEMY_ACCEL: f32 = 512.0;
+const ENEMY_FRICTION: f32 = 256.0;
 
 const DT: f32 = 1.0 / 60.0;
 
+// the player can shove everyone else around; skeletons and knights jostle each other evenly
+// (see World::spread_out)
+const PLAYER_PUSH: Priority = 2;
+const CROWD_PUSH: Priority = 1;
+
+// the player can wall off open floor to make chokepoints
+const BARRICADE: &str = "#";
+const FLOOR: &str = ".";
+
+// Battles happen in a generated cave, with gaps in the middle of each of its outer walls.
+// The cave wraps around, so the gaps in its walls lead out one side and back in the other.
+// Set SIM_SEED to replay the same cave.
+const CAVE_HEADER: &str = "cave 16
+bg 153 288 2 2
+bounds wrap
+====
+. o 153 288 16 16
+# s 136 288 16 16
+";
+const CAVE_SIZE: (usize, usize) = (32, 15);
+const CAVE_FILL: f32 = 0.42;
+const CAVE_STEPS: usize = 4;
+
 fn main() {
     #[cfg(not(target_arch = "wasm32"))]
     let source =
@@ -38,22 +71,26 @@ fn main() {
 }
 struct SimGame {
     pub humans: Vec<Knight>,
+    // leads every knight toward its nearest skeleton
+    pub enemy_field: FlowField,
 }
 
 struct Knight {
     pub health: u8,
-    pub pos: Vec2,
+    pub pos: WorldPos,
+    pub motion: Kinematic,
 }
 
 impl Knight {
-    fn find_enemy(&mut self, world: &mut World) -> Vec2 {
-        let mut closest = Vec2 {
+    fn find_enemy(&mut self, world: &mut World) -> WorldPos {
+        let mut closest = WorldPos {
             x: W as f32,
             y: H as f32,
         };
+        let level = &world.levels[world.current_level];
         for enemy in world.enemies.iter_mut() {
-            if (enemy.0.pos + (self.pos * -1.0)).mag_sq().sqrt()
-                < (closest + (self.pos * -1.0)).mag_sq().sqrt()
+            if level.offset(self.pos, enemy.0.pos).mag_sq()
+                < level.offset(self.pos, closest).mag_sq()
             {
                 closest = enemy.0.pos;
             }
@@ -64,7 +101,10 @@ impl Knight {
 
 impl SimGame {
     fn new(world: &mut World) -> Self {
-        let game = SimGame { humans: vec![] };
+        let game = SimGame {
+            humans: vec![],
+            enemy_field: world.level().flow_field(Connectivity::Eight),
+        };
         let player_start = *world.levels[world.current_level]
             .starts()
             .iter()
@@ -75,6 +115,7 @@ impl SimGame {
         game
     }
     fn simulate(&mut self, world: &mut World, input: &Input, dt: f32) {
+        let tsz = world.level().tile_size();
         if input.is_key_pressed(Key::KeyQ) {
             world.spawn_enemies();
         }
@@ -88,12 +129,39 @@ impl SimGame {
             return;
         }
 
-        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight) * PLAYER_SPEED * DT;
+        let dx = input.key_axis(Key::ArrowLeft, Key::ArrowRight);
         // now down means -y and up means +y!  beware!
-        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp) * PLAYER_SPEED * DT;
-        let dest = world.player.pos + Vec2 { x: dx, y: dy };
-        if !world.level().get_tile_at(dest).unwrap().solid {
-            world.player.pos = dest;
+        let dy = input.key_axis(Key::ArrowDown, Key::ArrowUp);
+        if dx > 0.0 {
+            world.player.dir = Dir::E;
+        }
+        if dx < 0.0 {
+            world.player.dir = Dir::W;
+        }
+        if dy > 0.0 {
+            world.player.dir = Dir::N;
+        }
+        if dy < 0.0 {
+            world.player.dir = Dir::S;
+        }
+        world
+            .player
+            .motion
+            .accelerate(Vec2 { x: dx, y: dy } * PLAYER_ACCEL);
+        let level = &world.levels[world.current_level];
+        let before = world.player.pos;
+        world
+            .player
+            .motion
+            .step(&mut world.player.pos, tsz as u16, level, dt);
+        // if the player came back on from the other side of the arena, take the camera along so the view
+        // doesn't jump
+        let jump = world.player.pos - (before + level.offset(before, world.player.pos));
+        world.camera.screen_pos[0] += jump.x;
+        world.camera.screen_pos[1] += jump.y;
+        if input.is_key_pressed(Key::KeyB) {
+            toggle_barricade(world);
+            world.level().refresh_flow_field(&mut self.enemy_field);
         }
 
         // for enemy in world.enemies.iter_mut() {
@@ -116,114 +184,92 @@ impl SimGame {
                     _ => panic!(),
                 };
             }
-            let enemy_dest = enemy.0.pos + (enemy.0.dir.to_vec2() * ENEMY_SPEED * dt);
-            if (enemy_dest.x >= 0.0
-                && enemy_dest.x <= (world.levels[world.current_level].width() * TILE_SZ) as f32)
-                && (enemy_dest.y > 0.0
-                    && enemy_dest.y
-                        <= (world.levels[world.current_level].height() * TILE_SZ) as f32)
-            {
-                enemy.0.pos = enemy_dest;
-            }
+            enemy
+                .0
+                .motion
+                .accelerate(enemy.0.dir.to_vec2() * ENEMY_ACCEL);
+            enemy.0.motion.step(
+                &mut enemy.0.pos,
+                tsz as u16,
+                &world.levels[world.current_level],
+                dt,
+            );
         }
 
+        let mut goals: Vec<_> = world
+            .enemies
+            .iter()
+            .map(|e| world.level().world_to_grid(e.0.pos))
+            .collect();
+        goals.sort();
+        goals.dedup();
+        world.level().set_flow_goals(&mut self.enemy_field, &goals);
         for human in self.humans.iter_mut() {
-            let monster_pos = human.find_enemy(world);
-            let human_pos = human.pos;
-            let mut direction = Vec2 { x: 0.0, y: 0.0 };
-            direction.x = monster_pos.x - human_pos.x;
-            direction.y = monster_pos.y - human_pos.y;
-            let normalized_direction = direction.normalize();
-            human.pos += normalized_direction * ENEMY_SPEED * dt;
+            // follow the field around any walls; once on a skeleton's tile (or if none can be reached) go straight at it
+            let normalized_direction =
+                match world.level().flow_direction(&self.enemy_field, human.pos) {
+                    Some(heading) => heading,
+                    None => {
+                        let monster_pos = human.find_enemy(world);
+                        let to_monster = world.level().offset(human.pos, monster_pos);
+                        if to_monster.mag_sq() > 0.0 {
+                            to_monster.normalize()
+                        } else {
+                            Vec2 { x: 0.0, y: 0.0 }
+                        }
+                    }
+                };
+            human.motion.accelerate(normalized_direction * ENEMY_ACCEL);
+            human
+                .motion
+                .step(&mut human.pos, tsz as u16, world.level(), dt);
         }
 
         let lw = world.level().width();
         let lh = world.level().height();
 
-        while world.player.pos.x
-            > world.camera.screen_pos[0] + world.camera.screen_size[0] - SCREEN_FAST_MARGIN
-        {
+        // scroll until the player is at least SCREEN_FAST_MARGIN away from the edges of the view
+        let on_screen = |world: &World| world.camera.to_screen(world.player.pos);
+        while on_screen(world).x > world.camera.screen_size[0] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] += 1.0;
         }
-        while world.player.pos.x < world.camera.screen_pos[0] + SCREEN_FAST_MARGIN {
+        while on_screen(world).x < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[0] -= 1.0;
         }
-        while world.player.pos.y
-            > world.camera.screen_pos[1] + world.camera.screen_size[1] - SCREEN_FAST_MARGIN
-        {
+        while on_screen(world).y > world.camera.screen_size[1] - SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] += 1.0;
         }
-        while world.player.pos.y < world.camera.screen_pos[1] + SCREEN_FAST_MARGIN {
+        while on_screen(world).y < SCREEN_FAST_MARGIN {
             world.camera.screen_pos[1] -= 1.0;
         }
-        world.camera.screen_pos[0] =
-            world.camera.screen_pos[0].clamp(0.0, (lw * TILE_SZ).max(W) as f32 - W as f32);
-        world.camera.screen_pos[1] =
-            world.camera.screen_pos[1].clamp(0.0, (lh * TILE_SZ).max(H) as f32 - H as f32);
+        // a wrapped level has no edges to stop the camera at
+        if world.level().bounds() != OutOfBounds::Wrap {
+            world.camera.screen_pos[0] =
+                world.camera.screen_pos[0].clamp(0.0, (lw * tsz).max(W) as f32 - W as f32);
+            world.camera.screen_pos[1] =
+                world.camera.screen_pos[1].clamp(0.0, (lh * tsz).max(H) as f32 - H as f32);
+        }
 
         let mut contacts = Vec::new();
         let p_rect = Rect {
-            x: world.player.pos.x - (TILE_SZ / 2) as f32,
-            y: world.player.pos.y - (TILE_SZ / 2) as f32,
-            w: (TILE_SZ) as u16,
-            h: (TILE_SZ) as u16,
+            x: world.player.pos.x - (tsz / 2) as f32,
+            y: world.player.pos.y - (tsz / 2) as f32,
+            w: (tsz) as u16,
+            h: (tsz) as u16,
         };
         let player = [p_rect];
-        let enemy_rect: Vec<_> = world.enemies.iter().map(|e| make_rect(e.0.pos)).collect();
+        let enemy_rect: Vec<_> = world
+            .enemies
+            .iter()
+            .map(|e| make_rect(e.0.pos, tsz))
+            .collect();
         generate_contact(&player, &enemy_rect, &mut contacts);
 
-        let knight_rect: Vec<_> = self.humans.iter().map(|e| make_rect(e.pos)).collect();
+        let knight_rect: Vec<_> = self.humans.iter().map(|e| make_rect(e.pos, tsz)).collect();
         let mut knight_contacts: Vec<Contact>;
         knight_contacts = vec![];
         generate_contact(&knight_rect, &enemy_rect, &mut knight_contacts);
 
-        // Tile and Player contacts
-        let mut tile_contacts = Vec::new();
-        generate_tile_contact(&[player[0]], world.level(), &mut tile_contacts);
-
-        // Tile and Enemy contacts
-        let mut tile_enemy_contacts = Vec::new();
-        generate_tile_contact(&enemy_rect, world.level(), &mut tile_enemy_contacts);
-
-        // Tile and Knight contacts
-        let mut tile_knight_contacts = Vec::new();
-        generate_tile_contact(&knight_rect, world.level(), &mut tile_knight_contacts);
-
-        // Contact Resolution for player vs. world
-        tile_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_contacts {
-            world.player.pos += find_displacement(p_rect, contact.b_rect);
-        }
-
-        // Contact Resolution for enemies vs. world
-        tile_enemy_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_enemy_contacts {
-            world.enemies[contact.a_index].0.pos +=
-                find_displacement(enemy_rect[contact.a_index], contact.b_rect);
-        }
-
-        // Contact Resolution for knight vs. world
-        tile_knight_contacts.sort_by(|a, b| {
-            b.displacement
-                .mag_sq()
-                .partial_cmp(&a.displacement.mag_sq())
-                .unwrap()
-        });
-        for contact in tile_knight_contacts {
-            self.humans[contact.a_index].pos +=
-                find_displacement(knight_rect[contact.a_index], contact.b_rect);
-        }
-
         // For deleting enemies, it's best to add the enemy to a "to_remove" vec, and then remove those enemies after this loop is all done.
         contacts.sort_by(|a, b| {
             b.displacement
@@ -255,6 +301,15 @@ impl SimGame {
         for i in knight_removable.iter().rev() {
             self.humans.swap_remove(*i);
         }
+        let knights: Vec<_> = self
+            .humans
+            .iter()
+            .map(|k| Body::centered(k.pos, tsz as u16, tsz as u16, CROWD_PUSH))
+            .collect();
+        let moved = world.spread_out(PLAYER_PUSH, CROWD_PUSH, &knights);
+        for (knight, by) in self.humans.iter_mut().zip(moved) {
+            knight.pos += by;
+        }
     }
 }
 
@@ -263,32 +318,41 @@ impl engine::Game for SimGame {
         self.simulate(world, input, DT);
     }
     fn render(&mut self, world: &mut engine::World, frend: &mut Immediate) {
+        let tsz = world.level().tile_size();
         // make this exactly as big as we need
         frend.sprite_group_set_camera(0, world.camera);
 
-        world.level().render_immediate(frend);
+        world.level().render_visible(frend, &world.camera);
+        // on a wrapped level, draw everything at whichever copy of its position is closest to the view
+        let view = world.camera.to_world(ScreenPos {
+            x: W as f32 / 2.0,
+            y: H as f32 / 2.0,
+        });
+        let near_view = |pos: WorldPos| view + world.level().offset(view, pos);
 
+        let player_pos = near_view(world.player.pos);
         frend.draw_sprite(
             0,
             Transform {
-                w: TILE_SZ as u16,
-                h: TILE_SZ as u16,
-                x: world.player.pos.x,
-                y: world.player.pos.y,
+                w: tsz as u16,
+                h: tsz as u16,
+                x: player_pos.x,
+                y: player_pos.y,
                 rot: 0.0,
             },
             PLAYER.with_depth(2),
         );
 
         for enemy in world.enemies.iter() {
-            if enemy.1 == 1 {
+            if enemy.1 > 0 {
+                let pos = near_view(enemy.0.pos);
                 frend.draw_sprite(
                     0,
                     Transform {
-                        w: TILE_SZ as u16,
-                        h: TILE_SZ as u16,
-                        x: enemy.0.pos.x,
-                        y: enemy.0.pos.y,
+                        w: tsz as u16,
+                        h: tsz as u16,
+                        x: pos.x,
+                        y: pos.y,
                         rot: 0.0,
                     },
                     ENEMY.with_depth(3),
@@ -299,13 +363,14 @@ impl engine::Game for SimGame {
         }
 
         for knight in self.humans.iter() {
+            let pos = near_view(knight.pos);
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
-                    x: knight.pos.x,
-                    y: knight.pos.y,
+                    w: tsz as u16,
+                    h: tsz as u16,
+                    x: pos.x,
+                    y: pos.y,
                     rot: 0.0,
                 },
                 KNIGHT1.with_depth(2),
@@ -317,8 +382,8 @@ impl engine::Game for SimGame {
             frend.draw_sprite(
                 0,
                 Transform {
-                    w: TILE_SZ as u16,
-                    h: TILE_SZ as u16,
+                    w: tsz as u16,
+                    h: tsz as u16,
                     x: world.player.pos.x,
                     y: world.player.pos.y,
                     rot: 0.0,
@@ -353,15 +418,15 @@ impl engine::Game for SimGame {
                     repeat: frenderer::nineslice::Repeat::Tile,
                 },
             );
-            let pause_x = W as f32 / 2.0 - 8.0 * TILE_SZ as f32;
-            let pause_y = H as f32 / 2.0 - 3.0 * TILE_SZ as f32;
+            let pause_x = W as f32 / 2.0 - 8.0 * UI_SZ as f32;
+            let pause_y = H as f32 / 2.0 - 3.0 * UI_SZ as f32;
             frend.draw_nineslice(
                 1,
                 &nine_tiled,
                 pause_x,
                 pause_y,
-                16.0 * TILE_SZ as f32,
-                6.0 * TILE_SZ as f32,
+                16.0 * UI_SZ as f32,
+                6.0 * UI_SZ as f32,
                 0,
             );
 
@@ -380,11 +445,11 @@ impl engine::Game for SimGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 3.0 * TILE_SZ as f32,
-                    (H / 2) as f32 + 2.5 * TILE_SZ as f32,
+                    (W / 2) as f32 - 3.0 * UI_SZ as f32,
+                    (H / 2) as f32 + 2.5 * UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
             );
             text = "unpause: Esc";
             frend.draw_text(
@@ -392,11 +457,11 @@ impl engine::Game for SimGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 3.25 * TILE_SZ as f32,
-                    (H / 2) as f32 - 2.0 * TILE_SZ as f32,
+                    (W / 2) as f32 - 3.25 * UI_SZ as f32,
+                    (H / 2) as f32 - 2.0 * UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
             );
             text = "q: spawn skeletons";
             frend.draw_text(
@@ -404,11 +469,11 @@ impl engine::Game for SimGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 4.5 * TILE_SZ as f32,
-                    (H / 2) as f32 + 1.0 * TILE_SZ as f32,
+                    (W / 2) as f32 - 4.5 * UI_SZ as f32,
+                    (H / 2) as f32 + 1.0 * UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
             );
             text = "e: spawn knights";
             frend.draw_text(
@@ -416,11 +481,23 @@ impl engine::Game for SimGame {
                 &font,
                 text,
                 [
-                    (W / 2) as f32 - 4.5 * TILE_SZ as f32,
-                    (H / 2) as f32 + 0.0 * TILE_SZ as f32,
+                    (W / 2) as f32 - 4.5 * UI_SZ as f32,
+                    (H / 2) as f32 + 0.0 * UI_SZ as f32,
                 ],
                 0,
-                (TILE_SZ / 2) as f32,
+                (UI_SZ / 2) as f32,
+            );
+            text = "b: build barricade";
+            frend.draw_text(
+                1,
+                &font,
+                text,
+                [
+                    (W / 2) as f32 - 4.5 * UI_SZ as f32,
+                    (H / 2) as f32 - 1.0 * UI_SZ as f32,
+                ],
+                0,
+                (UI_SZ / 2) as f32,
             );
         }
     }
@@ -436,11 +513,32 @@ impl engine::Game for SimGame {
             Some("tiles-sprites"),
         );
 
-        let levels = vec![Level::from_str(
-            &cache
-                .load::<String>("level3")
-                .expect("Couldn't access level3.txt")
-                .read(),
+        let seed = std::env::var("SIM_SEED")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_else(rand::random);
+        let (cave_w, cave_h) = CAVE_SIZE;
+        // the player comes in on the left, and there are gaps in the middle of every side
+        let mut openings = vec![(0, cave_h / 2), (0, cave_h / 2 - 1), (0, cave_h / 2 + 1)];
+        for y in cave_h / 2 - 1..=cave_h / 2 + 1 {
+            openings.push((cave_w - 1, y));
+        }
+        for x in cave_w / 2 - 2..cave_w / 2 + 2 {
+            openings.extend([(x, 0), (x, cave_h - 1)]);
+        }
+        let settings = CaveSettings {
+            width: cave_w,
+            height: cave_h,
+            fill: CAVE_FILL,
+            steps: CAVE_STEPS,
+            openings,
+            seed,
+        };
+        let levels = vec![cave::cave_level(
+            &settings,
+            CAVE_HEADER,
+            BARRICADE,
+            FLOOR,
             0,
             0,
         )];
@@ -449,8 +547,8 @@ impl engine::Game for SimGame {
             screen_pos: [0.0, 0.0],
             screen_size: [W as f32, H as f32], // 512x240
         };
-        let sprite_estimate =
-            levels[current_level].sprite_count() + levels[current_level].starts().len();
+        let sprite_estimate = levels[current_level].sprite_count_for_view(&camera)
+            + levels[current_level].starts().len();
         // tile sprite group: 0
         renderer.sprite_group_add(&tile_tex, sprite_estimate, camera);
         // HUD sprite group: 1
@@ -465,40 +563,62 @@ impl engine::Game for SimGame {
         world.set_levels(levels);
         world.set_current_level(current_level);
         world.set_enemies(vec![]);
+        world.set_enemy_motion(Kinematic::new(ENEMY_SPEED, ENEMY_FRICTION));
         world.set_player(Pos {
             pos: player_start,
             dir: Dir::S,
+            motion: Kinematic::new(PLAYER_SPEED, PLAYER_FRICTION),
         });
         SimGame::new(world)
     }
 }
 
+fn toggle_barricade(world: &mut World) {
+    // build on the tile the player is facing, or knock down a barricade that was built there
+    let tsz = world.level().tile_size();
+    let target = world.player.pos + world.player.dir.to_vec2() * tsz as f32;
+    let level = &mut world.levels[world.current_level];
+    let coord = level.world_to_grid(target);
+    match level.tile_symbol(coord) {
+        Some(FLOOR) => {
+            level.set_tile(coord, BARRICADE);
+        }
+        Some(BARRICADE) if level.is_modified(coord) => {
+            level.set_tile(coord, FLOOR);
+        }
+        _ => {}
+    }
+}
+
 fn spawn_humans(world: &mut World, game: &mut SimGame) {
     let mut rng = rand::thread_rng();
-    let mut randx = rng
-        .gen_range(2 * TILE_SZ..world.levels[world.current_level].width() * TILE_SZ - 2 * TILE_SZ);
-    let mut randy = rng
-        .gen_range(2 * TILE_SZ..world.levels[world.current_level].height() * TILE_SZ - 2 * TILE_SZ);
-    while ((randx as f32 - world.player.pos.x).abs() < 48.0)
-        && ((randy as f32 - world.player.pos.y).abs() < 48.0)
-        && !world
+    let tsz = world.level().tile_size();
+    let mut randx =
+        rng.gen_range(2 * tsz..world.levels[world.current_level].width() * tsz - 2 * tsz);
+    let mut randy =
+        rng.gen_range(2 * tsz..world.levels[world.current_level].height() * tsz - 2 * tsz);
+    // try again if that's within three tiles of the player or inside a wall
+    let near = 3.0 * tsz as f32;
+    while ((randx as f32 - world.player.pos.x).abs() < near)
+        && ((randy as f32 - world.player.pos.y).abs() < near)
+        || world
             .level()
-            .get_tile_at(Vec2 {
+            .get_tile_at(WorldPos {
                 x: randx as f32,
                 y: randy as f32,
             })
-            .unwrap()
             .solid
     {
-        randx = rng.gen_range(2..world.levels[world.current_level].width() * TILE_SZ);
-        randy = rng.gen_range(2..world.levels[world.current_level].height() * TILE_SZ);
+        randx = rng.gen_range(2..world.levels[world.current_level].width() * tsz);
+        randy = rng.gen_range(2..world.levels[world.current_level].height() * tsz);
     }
     let knight_data = Knight {
         health: 3,
-        pos: Vec2 {
+        pos: WorldPos {
             x: randx as f32,
             y: randy as f32,
         },
+        motion: Kinematic::new(ENEMY_SPEED, ENEMY_FRICTION),
     };
     game.humans.push(knight_data);
 }
@@ -519,22 +639,6 @@ fn generate_contact(group_a: &[Rect], group_b: &[Rect], contacts: &mut Vec<Conta
     }
 }
 
-fn generate_tile_contact(group_a: &[Rect], lvl: &Level, contacts: &mut Vec<Contact>) {
-    for (a_i, a_rect) in group_a.iter().enumerate() {
-        for (_, b_rect, _) in lvl.tiles_within(*a_rect).filter(|(_, _r, td)| td.solid) {
-            if let Some(overlap) = a_rect.overlap(b_rect) {
-                contacts.push(Contact {
-                    displacement: overlap,
-                    a_index: a_i,
-                    _a_rect: *a_rect,
-                    b_index: 0,
-                    b_rect,
-                });
-            }
-        }
-    }
-}
-
 fn find_displacement(a: Rect, b: Rect) -> Vec2 {
     if let Some(mut overlap) = a.overlap(b) {
         if overlap.x < overlap.y {
@@ -554,11 +658,12 @@ fn find_displacement(a: Rect, b: Rect) -> Vec2 {
     }
 }
 
-fn make_rect(position: Vec2) -> Rect {
+// A tile-sized rect centered on position.
+fn make_rect(position: WorldPos, tsz: usize) -> Rect {
     Rect {
-        x: position.x - (TILE_SZ / 2) as f32,
-        y: position.y - (TILE_SZ / 2) as f32,
-        w: TILE_SZ as u16,
-        h: TILE_SZ as u16,
+        x: position.x - (tsz / 2) as f32,
+        y: position.y - (tsz / 2) as f32,
+        w: tsz as u16,
+        h: tsz as u16,
     }
 }